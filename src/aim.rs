@@ -0,0 +1,342 @@
+//! Aim moving heads at points in space: inverse kinematics for pan/tilt
+//! yokes.
+//!
+//! Conventions: the world is right-handed with z up, and angles are in
+//! degrees. A fixture's own frame is that of its base, rotated from the
+//! world's by its Loc's orientation: pan (yaw) about z, then tilt (pitch)
+//! about y, then roll about x. In its own frame, a fixture whose yoke is at
+//! pan 0, tilt 0 points its beam straight out of its base, along +z; tilt
+//! swings the beam away from +z, and pan swings the plane it tilts in
+//! around z, from +x towards +y. A fixture standing on the floor with an
+//! orientation of all zeros therefore points at the ceiling, and one hung
+//! upside down (roll 180) points at the floor.
+//!
+//! Every direction but straight along the pan axis can be reached two ways,
+//! (pan, tilt) and (pan + 180, -tilt), and a yoke with more than 360 degrees
+//! of pan can reach each of those at more than one pan angle. The solver
+//! takes whichever reachable solution moves the yoke least.
+
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use device::*;
+use mixer::AnimatorPlugin;
+use mixer::Timepoint;
+use numeric::limit_bipolar_unit_f64;
+use numeric::limit_unipolar_unit_f64;
+use numeric::wrap_ring_bipolar_f64;
+use numeric::wrap_ring_unipolar_f64;
+use profile::*;
+use world::Loc;
+use world::Position;
+
+/// The mechanics of a pan/tilt yoke, as found in a fixture's spec sheet.
+#[deriving(Clone,PartialEq,Show)]
+pub struct YokeGeometry {
+    /// Total pan travel, e.g. 540.
+    pub pan_range_deg: f64,
+    /// Total tilt travel, e.g. 270.
+    pub tilt_range_deg: f64,
+    /// The pan angle at the center of the pan travel.
+    pub pan_home_deg: f64,
+    /// The tilt angle at the center of the tilt travel.
+    pub tilt_home_deg: f64,
+    /// Increasing pan values pan clockwise (from +y towards +x).
+    pub invert_pan: bool,
+    /// Increasing tilt values tilt the other way.
+    pub invert_tilt: bool,
+}
+
+/// A yoke position: pan and tilt angles, measured from the centers of their
+/// travel in the direction their attribute values increase.
+#[deriving(Clone,PartialEq,Show)]
+pub struct PanTilt {
+    pub pan_deg: f64,
+    pub tilt_deg: f64,
+}
+
+fn radians(deg: f64) -> f64 { deg * PI / 180.0 }
+fn degrees(rad: f64) -> f64 { rad * 180.0 / PI }
+
+/// Transform a direction in world coordinates into a fixture's own frame.
+pub fn world_to_fixture(loc: &Loc, v: &Position) -> Position {
+    let o = &loc.orientation;
+    // Undo the rotations in reverse order: pan, then tilt, then roll.
+    let (s, c) = radians(-o.pan).sin_cos();
+    let v = Position::new(c * v.x - s * v.y, s * v.x + c * v.y, v.z);
+    let (s, c) = radians(-o.tilt).sin_cos();
+    let v = Position::new(c * v.x + s * v.z, v.y, -s * v.x + c * v.z);
+    let (s, c) = radians(-o.roll).sin_cos();
+    Position::new(v.x, c * v.y - s * v.z, s * v.y + c * v.z)
+}
+
+impl YokeGeometry {
+    /// Every reachable yoke position that points the fixture at loc towards
+    /// target. If pan_wraps, pan is continuous and has no limits. Straight
+    /// along the pan axis any pan will do, so pan stays at current (or home).
+    pub fn solutions(&self, loc: &Loc, target: &Position, current: Option<&PanTilt>,
+            pan_wraps: bool) -> Vec<PanTilt> {
+        let v = Position::new(target.x - loc.position.x, target.y - loc.position.y,
+            target.z - loc.position.z);
+        let d = world_to_fixture(loc, &v.normalize());
+        let tilt = degrees(d.z.max(-1.0).min(1.0).acos());
+        let pan_sign = if self.invert_pan { -1.0 } else { 1.0 };
+        let pan = if d.x.abs() < 1e-9 && d.y.abs() < 1e-9 {
+            self.pan_home_deg + pan_sign * current.map_or(0.0, |c| c.pan_deg)
+        } else {
+            degrees(d.y.atan2(d.x))
+        };
+
+        let tilt_sign = if self.invert_tilt { -1.0 } else { 1.0 };
+        let mut found = Vec::new();
+        for &(p, t) in [(pan, tilt), (pan + 180.0, -tilt)].iter() {
+            let tilt_deg = tilt_sign * (t - self.tilt_home_deg);
+            if tilt_deg.abs() > self.tilt_range_deg / 2.0 + 1e-9 {
+                continue;
+            }
+            let pan_deg = pan_sign * (p - self.pan_home_deg);
+            if pan_wraps {
+                found.push(PanTilt { pan_deg: pan_deg, tilt_deg: tilt_deg });
+                continue;
+            }
+            // Try every turn of the pan that might be within its travel.
+            let turns = (self.pan_range_deg / 360.0).ceil() as int + 1;
+            for k in range(-turns, turns + 1) {
+                let pan_k = pan_deg + 360.0 * k as f64;
+                if pan_k.abs() <= self.pan_range_deg / 2.0 + 1e-9 {
+                    found.push(PanTilt { pan_deg: pan_k, tilt_deg: tilt_deg });
+                }
+            }
+        }
+        found
+    }
+
+    /// The yoke position that points the fixture at loc towards target with
+    /// the least movement from current (or from home, if None). Return None
+    /// if the target is out of reach.
+    pub fn aim(&self, loc: &Loc, target: &Position, current: Option<&PanTilt>,
+            pan_wraps: bool) -> Option<PanTilt> {
+        let home = PanTilt { pan_deg: 0.0, tilt_deg: 0.0 };
+        let from = current.unwrap_or(&home);
+        let movement = |pt: &PanTilt| {
+            let mut dp = (pt.pan_deg - from.pan_deg).abs();
+            if pan_wraps {
+                dp = dp % 360.0;
+                dp = dp.min(360.0 - dp);
+            }
+            dp + (pt.tilt_deg - from.tilt_deg).abs()
+        };
+        let mut best: Option<(f64, PanTilt)> = None;
+        for pt in self.solutions(loc, target, current, pan_wraps).move_iter() {
+            let m = movement(&pt);
+            let better = match best {
+                Some((bm, _)) => m < bm,
+                None => true,
+            };
+            if better {
+                best = Some((m, pt));
+            }
+        }
+        best.map(|(_, pt)| pt)
+    }
+
+    /// Convert a mechanical pan angle to a value for the pan attribute.
+    pub fn pan_value(&self, attr: &Attribute, pan_deg: f64) -> AttributeValue {
+        Continuous(angle_to_value(attr, pan_deg, self.pan_range_deg))
+    }
+
+    /// Convert a mechanical tilt angle to a value for the tilt attribute.
+    pub fn tilt_value(&self, attr: &Attribute, tilt_deg: f64) -> AttributeValue {
+        Continuous(angle_to_value(attr, tilt_deg, self.tilt_range_deg))
+    }
+
+    /// The inverse of pan_value.
+    pub fn pan_angle(&self, attr: &Attribute, value: AttributeValue) -> Option<f64> {
+        value_to_angle(attr, value, self.pan_range_deg)
+    }
+
+    /// The inverse of tilt_value.
+    pub fn tilt_angle(&self, attr: &Attribute, value: AttributeValue) -> Option<f64> {
+        value_to_angle(attr, value, self.tilt_range_deg)
+    }
+}
+
+/// Map an angle from the center of travel onto a continuous attribute. A
+/// ring attribute goes all the way round; a Euclidian one spans the travel.
+fn angle_to_value(attr: &Attribute, angle: f64, travel: f64) -> f64 {
+    let topo = attr.topo;
+    match (topo.is_ring(), topo.is_bipolar()) {
+        (true, true) => wrap_ring_bipolar_f64(angle / 180.0),
+        (true, false) => wrap_ring_unipolar_f64(angle / 360.0),
+        (false, true) => limit_bipolar_unit_f64(angle / (travel / 2.0)),
+        (false, false) => limit_unipolar_unit_f64(angle / travel + 0.5),
+    }
+}
+
+/// The inverse of angle_to_value. None if value isn't continuous.
+fn value_to_angle(attr: &Attribute, value: AttributeValue, travel: f64) -> Option<f64> {
+    let v = match value {
+        Continuous(v) => v,
+        Discrete(_) => return None,
+    };
+    let topo = attr.topo;
+    Some(match (topo.is_ring(), topo.is_bipolar()) {
+        (true, true) => v * 180.0,
+        (true, false) => v * 360.0,
+        (false, true) => v * travel / 2.0,
+        (false, false) => (v - 0.5) * travel,
+    })
+}
+
+/// A moving head to aim: where it hangs, its profile's yoke, and the paths
+/// to its pan and tilt endpoints within the layer's scene.
+pub struct AimTarget {
+    pub loc: Loc,
+    yoke: Rc<YokeGeometry>,
+    pub pan_path: DeviceTreePath,
+    pub tilt_path: DeviceTreePath,
+    /// Where this generator last put the yoke, for when the scene doesn't
+    /// say where it is.
+    pub last: Option<PanTilt>,
+}
+
+impl AimTarget {
+    /// Aim device, from the first of its locs, with its profile's yoke.
+    /// Return None if the profile has no yoke or the device no loc.
+    pub fn for_device(device: &Device, pan_path: DeviceTreePath,
+            tilt_path: DeviceTreePath) -> Option<AimTarget> {
+        let loc = match device.patches.iter().flat_map(|p| p.locs.iter()).next() {
+            Some(loc) => loc.clone(),
+            None => return None,
+        };
+        match device.profile.yoke {
+            Some(ref yoke) => Some(AimTarget {
+                loc: loc,
+                yoke: yoke.clone(),
+                pan_path: pan_path,
+                tilt_path: tilt_path,
+                last: None,
+            }),
+            None => None,
+        }
+    }
+
+    pub fn yoke(&self) -> &YokeGeometry {
+        &*self.yoke
+    }
+
+    /// Where the yoke is now, according to the pan and tilt endpoints in
+    /// scene; or failing that, where this target last put it.
+    fn current(&self, scene: &Rc<RefCell<DeviceTree>>) -> Option<PanTilt> {
+        let yoke = &*self.yoke;
+        let mut pan = None;
+        let mut tilt = None;
+        with_endpoint(scene, self.pan_path.as_slice(),
+            |e, a| pan = e.get_val().and_then(|v| yoke.pan_angle(a, v)));
+        with_endpoint(scene, self.tilt_path.as_slice(),
+            |e, a| tilt = e.get_val().and_then(|v| yoke.tilt_angle(a, v)));
+        match (pan, tilt) {
+            (Some(p), Some(t)) => Some(PanTilt { pan_deg: p, tilt_deg: t }),
+            _ => self.last.clone(),
+        }
+    }
+}
+
+/// Point a group of moving heads at a point, e.g. a follow spot or a focus
+/// point. Fixtures that can't reach it are left alone. Each takes the least
+/// movement from where its yoke is now: as a filter, according to the frame
+/// beneath; otherwise, according to the scene.
+pub struct AimGenerator {
+    pub point: Position,
+    pub targets: Vec<AimTarget>,
+}
+
+impl AnimatorPlugin for AimGenerator {
+    fn animate(&mut self, _time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+            frame: Option<&Rc<RefCell<DeviceTree>>>) {
+        for target in self.targets.mut_iter() {
+            let mut pan_wraps = false;
+            with_endpoint(scene, target.pan_path.as_slice(), |_, a| pan_wraps = a.topo.is_ring());
+            let current = target.current(frame.unwrap_or(scene));
+            let pt = match target.yoke.aim(&target.loc, &self.point,
+                    current.as_ref(), pan_wraps) {
+                Some(pt) => pt,
+                None => continue,
+            };
+            let yoke = &*target.yoke;
+            with_endpoint(scene, target.pan_path.as_slice(),
+                |e, a| e.set_val(yoke.pan_value(a, pt.pan_deg)));
+            with_endpoint(scene, target.tilt_path.as_slice(),
+                |e, a| e.set_val(yoke.tilt_value(a, pt.tilt_deg)));
+            target.last = Some(pt);
+        }
+    }
+}
+
+#[test]
+fn test_aim() {
+    use dmx::DmxUniverse;
+    use loader::load_profile;
+    use test_dimmer::{at, close, new_tree_root};
+    use world::Orientation;
+
+    let yoke = YokeGeometry { pan_range_deg: 540.0, tilt_range_deg: 270.0,
+        pan_home_deg: 0.0, tilt_home_deg: 0.0, invert_pan: false, invert_tilt: false };
+    let standing = Loc::new_origin();
+    let target = Position::new(1.0, 0.0, 1.0);
+
+    // From home, tilt over towards +x. But a yoke already panned most of the
+    // way around is closer to the flipped solution.
+    let pt = yoke.aim(&standing, &target, None, false).unwrap();
+    assert!(close(pt.pan_deg, 0.0) && close(pt.tilt_deg, 45.0));
+    let pt = yoke.aim(&standing, &target, Some(&PanTilt { pan_deg: 170.0, tilt_deg: 0.0 }), false).unwrap();
+    assert!(close(pt.pan_deg, 180.0) && close(pt.tilt_deg, -45.0));
+    let pt = yoke.aim(&standing, &target, Some(&PanTilt { pan_deg: -250.0, tilt_deg: 45.0 }), false).unwrap();
+    assert!(close(pt.pan_deg, -180.0) && close(pt.tilt_deg, -45.0));
+
+    // Hung upside down, straight down is straight out of the base; and 3m
+    // below and 3m along +y (pan 90) needs 45 degrees of tilt.
+    let hung = Loc { position: Position::new(0.0, 0.0, 3.0),
+        orientation: Orientation { pan: 0.0, tilt: 0.0, roll: 180.0 } };
+    let pt = yoke.aim(&hung, &Position::new(0.0, 0.0, 0.0), None, false).unwrap();
+    assert!(close(pt.tilt_deg, 0.0));
+    let pt = yoke.aim(&hung, &Position::new(0.0, 3.0, 0.0), None, false).unwrap();
+    assert!(close(pt.pan_deg.abs(), 90.0) && close(pt.tilt_deg.abs(), 45.0));
+
+    // A yoke that only tilts 90 degrees can't reach behind itself.
+    let short = YokeGeometry { tilt_range_deg: 90.0, ..yoke.clone() };
+    assert!(short.aim(&standing, &Position::new(1.0, 0.0, -1.0), None, false).is_none());
+
+    // Straight up the pan axis, pan stays where it is.
+    let pt = yoke.aim(&standing, &Position::new(0.0, 0.0, 5.0),
+        Some(&PanTilt { pan_deg: 100.0, tilt_deg: 30.0 }), false).unwrap();
+    assert!(close(pt.pan_deg, 100.0) && close(pt.tilt_deg, 0.0));
+
+    // A device takes its yoke from its profile, and moves least from where
+    // the scene says it is.
+    let p = load_profile(r#"{
+        "name": "Head", "nickname": "Head", "channels": 2,
+        "yoke": {"pan_range": 540, "tilt_range": 270},
+        "root": {"branch": "Head", "nickname": "Head", "children": [
+            {"attribute": "Pan", "nickname": "Pan",
+             "effect": ["Position", "Other", "Value"],
+             "topo": "continuous_euclidian_bipolar", "default": 0.0,
+             "dmx": {"offset": 0, "renderer": "float_bipolar"}},
+            {"attribute": "Tilt", "nickname": "Tilt",
+             "effect": ["Position", "Other", "Value"],
+             "topo": "continuous_euclidian_bipolar", "default": 0.0,
+             "dmx": {"offset": 1, "renderer": "float_bipolar"}}
+        ]}
+    }"#).ok().unwrap();
+    let univ = Rc::new(RefCell::new(DmxUniverse { id: 0, name: "U1".to_string(), frame: [0, ..512] }));
+    let mut d = patch(&p, new_tree_root(), 0, univ).unwrap();
+    assert!(AimTarget::for_device(&d, vec!(0), vec!(1)).is_none());
+    d.patches.get_mut(0).locs.push(standing.clone());
+    let mut aim = AimGenerator { point: target,
+        targets: vec!(AimTarget::for_device(&d, vec!(0), vec!(1)).unwrap()) };
+    with_endpoint(&d.root, [0], |e, _| e.set_val(Continuous(170.0 / 270.0)));
+    aim.animate(&at(0), &d.root, None);
+    let pt = aim.targets.get(0).last.clone().unwrap();
+    assert!(close(pt.pan_deg, 180.0) && close(pt.tilt_deg, -45.0));
+}
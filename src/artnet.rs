@@ -0,0 +1,299 @@
+//! Art-Net output for DmxUniverse frames.
+//!
+//! An ArtNetSender transmits each universe's frame as an ArtDmx packet over
+//! UDP to the destinations routed to it, unicast or broadcast. It can also
+//! poll the network for nodes (ArtPoll) and parse their ArtPollReplys.
+//!
+//! Art-Net addresses a universe with a 15-bit Port-Address: a 7-bit net, a
+//! 4-bit subnet, and a 4-bit universe. By default a DmxUniverse's id is taken
+//! to be its Port-Address.
+
+use std::fmt;
+use std::io::IoError;
+use std::io::IoResult;
+use std::io::net::ip::IpAddr;
+use std::io::net::ip::Ipv4Addr;
+use std::io::net::ip::SocketAddr;
+use std::io::net::udp::UdpSocket;
+use std::str;
+
+use dmx::DmxUniverse;
+
+/// The UDP port Art-Net nodes listen on.
+pub static ARTNET_PORT: u16 = 0x1936; // 6454
+
+/// Art-Net protocol revision 14.
+static PROTOCOL_VERSION: u8 = 14;
+
+static OP_POLL: u16 = 0x2000;
+static OP_POLL_REPLY: u16 = 0x2100;
+static OP_DMX: u16 = 0x5000;
+
+pub enum ArtNetError {
+    /// No route has been added for this universe id.
+    NoRoute(u32),
+    ArtNetIoError(IoError),
+}
+
+impl fmt::Show for ArtNetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NoRoute(id) => write!(f, "no Art-Net route for universe {}", id),
+            ArtNetIoError(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The 15-bit address of an Art-Net universe.
+#[deriving(Show,Clone,PartialEq)]
+pub struct PortAddress {
+    pub net: u8,      // 0-127
+    pub subnet: u8,   // 0-15
+    pub universe: u8, // 0-15
+}
+
+impl PortAddress {
+    /// Interpret the low 15 bits of a DmxUniverse id as a Port-Address.
+    pub fn from_universe_id(id: u32) -> PortAddress {
+        PortAddress {
+            net: ((id >> 8) & 0x7F) as u8,
+            subnet: ((id >> 4) & 0x0F) as u8,
+            universe: (id & 0x0F) as u8,
+        }
+    }
+
+    /// The SubUni byte of an ArtDmx packet: subnet in the high nibble.
+    fn sub_uni(&self) -> u8 {
+        ((self.subnet & 0x0F) << 4) | (self.universe & 0x0F)
+    }
+}
+
+/// Where to send one universe.
+pub struct ArtNetRoute {
+    pub universe_id: u32,
+    pub port_address: PortAddress,
+    /// Unicast node addresses, or a broadcast address such as
+    /// 2.255.255.255:6454, or both.
+    pub destinations: Vec<SocketAddr>,
+    /// The last sequence number sent, from 1 to 255. (0 would tell nodes to
+    /// disable resequencing, so we never send it.)
+    sequence: u8,
+}
+
+/// A node's answer to an ArtPoll. Only the fields a controller needs to
+/// route universes are parsed.
+#[deriving(Show,Clone)]
+pub struct ArtPollReply {
+    pub ip: IpAddr,
+    pub port: u16,
+    pub net_switch: u8,
+    pub sub_switch: u8,
+    pub short_name: String,
+    pub long_name: String,
+    pub num_ports: u16,
+    /// The low nibble of each output port's universe.
+    pub sw_out: [u8, ..4],
+}
+
+pub struct ArtNetSender {
+    socket: UdpSocket,
+    pub routes: Vec<ArtNetRoute>,
+}
+
+impl ArtNetSender {
+    /// Bind a socket for sending, e.g. to 0.0.0.0:6454. Broadcast is enabled.
+    pub fn bind(local: SocketAddr) -> IoResult<ArtNetSender> {
+        let mut socket = try!(UdpSocket::bind(local));
+        try!(socket.set_broadcast(true));
+        Ok(ArtNetSender { socket: socket, routes: Vec::new() })
+    }
+
+    /// Send universe_id to the given destinations, at the Port-Address implied
+    /// by its id.
+    pub fn add_route(&mut self, universe_id: u32, destinations: Vec<SocketAddr>) {
+        self.add_route_to(universe_id, PortAddress::from_universe_id(universe_id), destinations)
+    }
+
+    /// Send universe_id to the given destinations, at an explicit Port-Address.
+    /// Replaces any existing route for the universe.
+    pub fn add_route_to(&mut self, universe_id: u32, port_address: PortAddress,
+            destinations: Vec<SocketAddr>) {
+        self.routes.retain(|r| r.universe_id != universe_id);
+        self.routes.push(ArtNetRoute {
+            universe_id: universe_id,
+            port_address: port_address,
+            destinations: destinations,
+            sequence: 0,
+        });
+    }
+
+    /// Transmit a rendered universe frame as an ArtDmx packet to each of its
+    /// route's destinations.
+    pub fn send(&mut self, univ: &DmxUniverse) -> Result<(), ArtNetError> {
+        let route = match self.routes.mut_iter().find(|r| r.universe_id == univ.id) {
+            Some(r) => r,
+            None => return Err(NoRoute(univ.id)),
+        };
+        route.sequence = if route.sequence == 255 { 1 } else { route.sequence + 1 };
+        let packet = art_dmx_packet(&route.port_address, route.sequence, univ.frame.as_slice());
+        for dest in route.destinations.iter() {
+            match self.socket.sendto(packet.as_slice(), *dest) {
+                Ok(_) => (),
+                Err(e) => return Err(ArtNetIoError(e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Broadcast an ArtPoll, asking nodes to identify themselves. Their
+    /// replies arrive on this sender's socket; see receive_poll_replies.
+    pub fn poll(&mut self, broadcast: SocketAddr) -> IoResult<()> {
+        self.socket.sendto(art_poll_packet().as_slice(), broadcast)
+    }
+
+    /// Collect ArtPollReplys until nothing arrives for timeout_ms. Other
+    /// packets are ignored.
+    pub fn receive_poll_replies(&mut self, timeout_ms: u64) -> Vec<ArtPollReply> {
+        let mut replies = Vec::new();
+        let mut buf = [0u8, ..1024];
+        self.socket.set_read_timeout(Some(timeout_ms));
+        loop {
+            match self.socket.recvfrom(&mut buf) {
+                Ok((len, _)) => match parse_art_poll_reply(buf.slice_to(len)) {
+                    Some(r) => replies.push(r),
+                    None => ()
+                },
+                Err(_) => break, // timed out
+            }
+        }
+        self.socket.set_read_timeout(None);
+        replies
+    }
+}
+
+/// The 10-byte header shared by every Art-Net packet: ID and OpCode.
+fn header(op: u16) -> Vec<u8> {
+    let mut p = Vec::from_slice(b"Art-Net\0");
+    p.push((op & 0xFF) as u8); // OpCode is little-endian
+    p.push((op >> 8) as u8);
+    p
+}
+
+/// Build an ArtDmx packet. data must hold at most 512 channels; an odd
+/// length is padded with a zero, as the protocol requires an even length.
+pub fn art_dmx_packet(port: &PortAddress, sequence: u8, data: &[u8]) -> Vec<u8> {
+    let mut p = header(OP_DMX);
+    let len = data.len() + data.len() % 2;
+    p.push(0); // ProtVerHi
+    p.push(PROTOCOL_VERSION);
+    p.push(sequence);
+    p.push(0); // Physical port, informational only
+    p.push(port.sub_uni());
+    p.push(port.net & 0x7F);
+    p.push((len >> 8) as u8); // Length is big-endian
+    p.push((len & 0xFF) as u8);
+    p.push_all(data);
+    if len > data.len() {
+        p.push(0);
+    }
+    p
+}
+
+/// Build an ArtPoll packet asking nodes to reply only when polled.
+pub fn art_poll_packet() -> Vec<u8> {
+    let mut p = header(OP_POLL);
+    p.push(0); // ProtVerHi
+    p.push(PROTOCOL_VERSION);
+    p.push(0); // TalkToMe: no unsolicited replies, no diagnostics
+    p.push(0); // Priority of diagnostics, unused
+    p
+}
+
+/// Build the ArtPollReply this controller sends when it is polled. We have no
+/// DMX ports of our own, so we only identify ourselves.
+pub fn art_poll_reply_packet(ip: IpAddr, short_name: &str, long_name: &str) -> Vec<u8> {
+    let mut p = header(OP_POLL_REPLY);
+    p.grow(239 - p.len(), &0u8);
+    match ip {
+        Ipv4Addr(a, b, c, d) => {
+            *p.get_mut(10) = a;
+            *p.get_mut(11) = b;
+            *p.get_mut(12) = c;
+            *p.get_mut(13) = d;
+        },
+        _ => ()
+    }
+    *p.get_mut(14) = (ARTNET_PORT & 0xFF) as u8;
+    *p.get_mut(15) = (ARTNET_PORT >> 8) as u8;
+    copy_name(p.mut_slice(26, 26 + 18), short_name);
+    copy_name(p.mut_slice(44, 44 + 64), long_name);
+    *p.get_mut(200) = 1; // Style: StController
+    p
+}
+
+/// Copy a name into a fixed-size, NUL-terminated field, truncating it if
+/// necessary.
+fn copy_name(field: &mut [u8], name: &str) {
+    let bytes = name.as_bytes();
+    let n = if bytes.len() < field.len() { bytes.len() } else { field.len() - 1 };
+    for i in range(0, n) {
+        field[i] = bytes[i];
+    }
+}
+
+/// Read a NUL-terminated name from a fixed-size field.
+fn read_name(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    str::from_utf8_lossy(field.slice_to(end)).into_string()
+}
+
+/// Parse an ArtPollReply. Return None for any other packet.
+pub fn parse_art_poll_reply(p: &[u8]) -> Option<ArtPollReply> {
+    if p.len() < 207 || p.slice_to(8) != b"Art-Net\0"
+            || (p[8] as u16 | (p[9] as u16 << 8)) != OP_POLL_REPLY {
+        return None;
+    }
+    Some(ArtPollReply {
+        ip: Ipv4Addr(p[10], p[11], p[12], p[13]),
+        port: p[14] as u16 | (p[15] as u16 << 8),
+        net_switch: p[18],
+        sub_switch: p[19],
+        short_name: read_name(p.slice(26, 44)),
+        long_name: read_name(p.slice(44, 108)),
+        num_ports: (p[172] as u16 << 8) | p[173] as u16,
+        sw_out: [p[190], p[191], p[192], p[193]],
+    })
+}
+
+#[test]
+fn test_art_dmx_to_local_listener() {
+    // Port 0 lets the OS pick free ports.
+    let mut listener = UdpSocket::bind(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 }).unwrap();
+    let listen_addr = listener.socket_name().unwrap();
+    let mut sender = ArtNetSender::bind(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 }).unwrap();
+
+    // Net 1, subnet 2, universe 3.
+    let mut univ = DmxUniverse { id: 0x123, name: "U".to_string(), frame: [0, ..512] };
+    univ.frame[0] = 255;
+    sender.add_route(univ.id, vec!(listen_addr));
+    sender.send(&univ).ok().unwrap();
+    sender.send(&univ).ok().unwrap();
+
+    let mut buf = [0u8, ..1024];
+    let (len, _) = listener.recvfrom(&mut buf).unwrap();
+    assert_eq!(len, 18 + 512);
+    assert_eq!(buf.slice_to(8), b"Art-Net\0");
+    assert_eq!((buf[8], buf[9]), (0x00, 0x50));
+    assert_eq!(buf[12], 1); // first sequence number
+    assert_eq!((buf[14], buf[15]), (0x23, 0x01));
+    assert_eq!((buf[16], buf[17]), (0x02, 0x00));
+    assert_eq!(buf[18], 255);
+
+    let (_, _) = listener.recvfrom(&mut buf).unwrap();
+    assert_eq!(buf[12], 2);
+
+    let reply = art_poll_reply_packet(Ipv4Addr(10, 0, 0, 5), "sinuous", "libsinuous controller");
+    let parsed = parse_art_poll_reply(reply.as_slice()).unwrap();
+    assert_eq!(parsed.short_name.as_slice(), "sinuous");
+    assert_eq!(parsed.ip, Ipv4Addr(10, 0, 0, 5));
+}
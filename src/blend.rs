@@ -252,3 +252,78 @@ pub fn fblend_ring_uni_mean(a: f64, b: f64) -> f64 {
 pub fn fblend_ring_bi_mean(a: f64, b: f64) -> f64 {
     fblend_ring_mean(wrap_ring_bipolar_f64, a, b)
 }
+
+
+// -----------------------------------------------------------------------------
+// Weighted mixers
+//
+// Unlike the blenders above, these take a weight, alpha, in [0.0..1.0]. An
+// alpha of 0.0 yields b, an alpha of 1.0 yields a, and values between move
+// from b toward a. Out-of-range alphas are clamped. Fades, cue transitions and
+// layer opacity are all weighted mixes.
+
+/// Mix two Euclidian values linearly.
+pub fn fblend_euclid_mix(a: f64, b: f64, alpha: f64) -> f64 {
+    let w = limit_unipolar_unit_f64(alpha);
+    b + w * (a - b)
+}
+
+/// Private impl for the two functions below. Move from b toward a by the
+/// shortest arc around a ring with the given period.
+fn fblend_ring_mix(f: fn(f64) -> f64, period: f64, a: f64, b: f64, alpha: f64) -> f64 {
+    let w = limit_unipolar_unit_f64(alpha);
+    let mut d = f(a) - f(b);
+    if d > period / 2.0 {
+        d -= period;
+    } else if d < -period / 2.0 {
+        d += period;
+    }
+    f(f(b) + w * d)
+}
+
+/// Mix two unsigned ring values, taking the short way around. Output is in
+/// the range [0..1].
+pub fn fblend_ring_uni_mix(a: f64, b: f64, alpha: f64) -> f64 {
+    fblend_ring_mix(wrap_ring_unipolar_f64, 1.0, a, b, alpha)
+}
+
+/// Mix two signed ring values, taking the short way around. Output is in the
+/// range [-1..1].
+pub fn fblend_ring_bi_mix(a: f64, b: f64, alpha: f64) -> f64 {
+    fblend_ring_mix(wrap_ring_bipolar_f64, 2.0, a, b, alpha)
+}
+
+/// Mix two Euclidian integer values linearly, rounding to the nearest
+/// integer and limiting the result to [minimum..maximum].
+pub fn iblend_euclid_mix(a: i64, b: i64, alpha: f64, minimum: i64, maximum: i64) -> i64 {
+    let w = limit_unipolar_unit_f64(alpha);
+    let n = b as f64 + w * (a - b) as f64;
+    limit_euclid_i64(n.round() as i64, minimum, maximum)
+}
+
+/// Mix two integer ring values, taking the short way around the ring
+/// [minimum..maximum] and rounding to the nearest integer. Antipodes take the
+/// upward route.
+pub fn iblend_ring_mix(a: i64, b: i64, alpha: f64, minimum: i64, maximum: i64) -> i64 {
+    let w = limit_unipolar_unit_f64(alpha);
+    let rng = maximum - minimum + 1;
+    let aa = wrap_ring_i64(a, minimum, maximum);
+    let bb = wrap_ring_i64(b, minimum, maximum);
+    let mut d = aa - bb;
+    if 2 * d > rng {
+        d -= rng;
+    } else if 2 * d <= -rng {
+        d += rng;
+    }
+    wrap_ring_i64(bb + (w * d as f64).round() as i64, minimum, maximum)
+}
+
+/// For values between which interpolation is meaningless: hold b until alpha
+/// reaches one half, then snap to a. The range is ignored.
+pub fn iblend_snap_mix(a: i64, b: i64, alpha: f64, _minimum: i64, _maximum: i64) -> i64 {
+    if alpha >= 0.5 {
+        a
+    } else {
+        b
+    }
+}
@@ -0,0 +1,422 @@
+//! Colorspace conversion: set one abstract color on any fixture, whatever
+//! emitters or filters its profile declares.
+//!
+//! Colors are held as linear RGB: the levels of ideal red, green and blue
+//! emitters, each in [0.0..1.0]. A fixture's color attributes are the
+//! endpoints whose effect is Color, and their EffectSubtype names the
+//! colorspace. Within a profile, a colorspace's attributes must appear in the
+//! order of its name: red, green, blue, then amber and white; hue,
+//! saturation, then brightness or lightness; intensity before filters; and
+//! cyan, magenta, yellow for three subtractive filters.
+
+use std::cell::RefCell;
+use std::f64::INFINITY;
+use std::rc::Rc;
+
+use device::*;
+use effect::Color;
+use effect::Colorspace3x;
+use effect::ColorspaceHsb;
+use effect::ColorspaceHsl;
+use effect::ColorspaceI;
+use effect::ColorspaceI3x;
+use effect::ColorspaceRgb;
+use effect::ColorspaceRgbaw;
+use effect::ColorspaceRgbi;
+use effect::ColorspaceRgbw;
+use effect::EffectSubtype;
+use generator::fit_to_topo;
+use mixer::AnimatorPlugin;
+use mixer::Timepoint;
+use numeric::limit_unipolar_unit_f64;
+use numeric::wrap_ring_unipolar_f64;
+use profile::*;
+
+/// A color, as linear RGB.
+#[deriving(Clone,PartialEq,Show)]
+pub struct Rgb {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+}
+
+impl Rgb {
+    pub fn new(r: f64, g: f64, b: f64) -> Rgb {
+        Rgb {
+            r: limit_unipolar_unit_f64(r),
+            g: limit_unipolar_unit_f64(g),
+            b: limit_unipolar_unit_f64(b),
+        }
+    }
+
+    /// From hue (a ring: 0.0 is red, 1/3 green, 2/3 blue), saturation and
+    /// brightness.
+    pub fn from_hsb(h: f64, s: f64, v: f64) -> Rgb {
+        let h6 = wrap_ring_unipolar_f64(h) * 6.0;
+        let sector = h6.floor();
+        let f = h6 - sector;
+        let (p, q, t) = (v * (1.0 - s), v * (1.0 - s * f), v * (1.0 - s * (1.0 - f)));
+        match sector as int {
+            0 => Rgb::new(v, t, p),
+            1 => Rgb::new(q, v, p),
+            2 => Rgb::new(p, v, t),
+            3 => Rgb::new(p, q, v),
+            4 => Rgb::new(t, p, v),
+            _ => Rgb::new(v, p, q),
+        }
+    }
+
+    /// From hue, saturation and lightness.
+    pub fn from_hsl(h: f64, s: f64, l: f64) -> Rgb {
+        let v = l + s * l.min(1.0 - l);
+        let sv = if v == 0.0 { 0.0 } else { 2.0 * (1.0 - l / v) };
+        Rgb::from_hsb(h, sv, v)
+    }
+
+    /// From CIE 1931 chromaticity (x, y) and luminance, using the sRGB
+    /// primaries. Colors outside the gamut are desaturated to fit, and ones
+    /// too bright for it are dimmed.
+    pub fn from_xy(x: f64, y: f64, luminance: f64) -> Rgb {
+        if y <= 0.0 {
+            return Rgb::new(0.0, 0.0, 0.0);
+        }
+        let cy = luminance;
+        let cx = x * cy / y;
+        let cz = (1.0 - x - y) * cy / y;
+        let mut r = 3.2406 * cx - 1.5372 * cy - 0.4986 * cz;
+        let mut g = -0.9689 * cx + 1.8758 * cy + 0.0415 * cz;
+        let mut b = 0.0557 * cx - 0.2040 * cy + 1.0570 * cz;
+        let lowest = r.min(g).min(b);
+        if lowest < 0.0 {
+            r -= lowest;
+            g -= lowest;
+            b -= lowest;
+        }
+        let highest = r.max(g).max(b);
+        if highest > 1.0 {
+            r /= highest;
+            g /= highest;
+            b /= highest;
+        }
+        Rgb::new(r, g, b)
+    }
+
+    /// (hue, saturation, brightness)
+    pub fn to_hsb(&self) -> (f64, f64, f64) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let delta = max - min;
+        let s = if max == 0.0 { 0.0 } else { delta / max };
+        (self.hue(max, delta), s, max)
+    }
+
+    /// (hue, saturation, lightness)
+    pub fn to_hsl(&self) -> (f64, f64, f64) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let delta = max - min;
+        let l = (max + min) / 2.0;
+        let s = if l == 0.0 || l == 1.0 { 0.0 } else { delta / (1.0 - (2.0 * l - 1.0).abs()) };
+        (self.hue(max, delta), s, l)
+    }
+
+    fn hue(&self, max: f64, delta: f64) -> f64 {
+        if delta == 0.0 {
+            return 0.0;
+        }
+        let h = if max == self.r {
+            (self.g - self.b) / delta
+        } else if max == self.g {
+            (self.b - self.r) / delta + 2.0
+        } else {
+            (self.r - self.g) / delta + 4.0
+        };
+        wrap_ring_unipolar_f64(h / 6.0)
+    }
+}
+
+/// Convert a color to the levels of a colorspace's attributes, in their
+/// declared order. Return None for colorspaces whose filters can't be
+/// inferred from their name alone, such as a pair of dichros.
+pub fn emitter_levels(space: EffectSubtype, c: &Rgb) -> Option<Vec<f64>> {
+    let max = c.r.max(c.g).max(c.b);
+    // Normalize the color to full brightness, for fixtures with a separate
+    // intensity.
+    let unit = if max == 0.0 {
+        Rgb::new(0.0, 0.0, 0.0)
+    } else {
+        Rgb::new(c.r / max, c.g / max, c.b / max)
+    };
+    match space {
+        ColorspaceRgb => Some(vec!(c.r, c.g, c.b)),
+        ColorspaceRgbi => Some(vec!(unit.r, unit.g, unit.b, max)),
+        ColorspaceRgbw => {
+            let w = c.r.min(c.g).min(c.b);
+            Some(vec!(c.r - w, c.g - w, c.b - w, w))
+        },
+        ColorspaceRgbaw => {
+            // Amber is taken as half as green as it is red.
+            let w = c.r.min(c.g).min(c.b);
+            let (r, g, b) = (c.r - w, c.g - w, c.b - w);
+            let a = r.min(2.0 * g);
+            Some(vec!(r - a, g - a / 2.0, b, a, w))
+        },
+        ColorspaceHsb => {
+            let (h, s, v) = c.to_hsb();
+            Some(vec!(h, s, v))
+        },
+        ColorspaceHsl => {
+            let (h, s, l) = c.to_hsl();
+            Some(vec!(h, s, l))
+        },
+        // Subtractive filters in front of a white source.
+        Colorspace3x => Some(vec!(1.0 - c.r, 1.0 - c.g, 1.0 - c.b)),
+        ColorspaceI3x => Some(vec!(max, 1.0 - unit.r, 1.0 - unit.g, 1.0 - unit.b)),
+        ColorspaceI => Some(vec!(max)),
+        _ => None
+    }
+}
+
+/// Find a fixture's color attributes: the colorspace of the first endpoint
+/// below root whose effect is Color, and the paths to all the endpoints in
+/// that colorspace, in tree order. Switches are searched in their current
+/// selection only.
+pub fn color_paths(root: &Rc<RefCell<DeviceTree>>) -> Option<(EffectSubtype, Vec<DeviceTreePath>)> {
+    fn walk(node: &Rc<RefCell<DeviceTree>>, path: &mut DeviceTreePath,
+            found: &mut Vec<(EffectSubtype, DeviceTreePath)>) {
+        let children: Vec<(uint, Rc<RefCell<DeviceTree>>)> = match *node.borrow() {
+            DeviceTreeEndpoint(ref e) => {
+                match *e.attribute.borrow() {
+                    ProfileGraphAttribute(ref a) => match a.effect {
+                        (Color, space, _) => found.push((space, path.clone())),
+                        _ => ()
+                    },
+                    _ => ()
+                }
+                return;
+            },
+            DeviceTreeBranch(ref b) => b.children.iter().enumerate()
+                .map(|(i, c)| (i, c.clone())).collect(),
+            DeviceTreeSwitch(ref s) if s.selection < s.children.len() =>
+                vec!((s.selection, s.children.get(s.selection).clone())),
+            DeviceTreeSwitch(_) => Vec::new(),
+        };
+        for &(i, ref child) in children.iter() {
+            path.push(i);
+            walk(child, path, found);
+            path.pop();
+        }
+    }
+    let mut found = Vec::new();
+    walk(root, &mut Vec::new(), &mut found);
+    if found.is_empty() {
+        return None;
+    }
+    let space = match *found.get(0) { (s, _) => s };
+    let paths = found.move_iter()
+        .filter(|&(s, _)| s as uint == space as uint)
+        .map(|(_, p)| p)
+        .collect();
+    Some((space, paths))
+}
+
+/// What a color wheel looks like at one of its indexed positions.
+#[deriving(Clone,PartialEq,Show)]
+pub struct WheelSlot {
+    pub color: Rgb,
+    /// A split color: half of each of two adjacent slots.
+    pub split: bool,
+}
+
+/// Pick a color wheel position for a fixture, and remember it: a wheel that
+/// flicks between two slots during a slow fade looks terrible, so the current
+/// slot is kept until another is closer by more than hysteresis.
+pub struct WheelChooser {
+    /// How much closer, as a distance between colors, another slot must be.
+    pub hysteresis: f64,
+    /// Consider split colors. Half slots show two colors at once, which some
+    /// designers would rather never see.
+    pub allow_splits: bool,
+    pub current: Option<uint>,
+}
+
+impl WheelChooser {
+    pub fn new(hysteresis: f64, allow_splits: bool) -> WheelChooser {
+        WheelChooser { hysteresis: hysteresis, allow_splits: allow_splits, current: None }
+    }
+
+    /// The index of the slot closest in hue and saturation to color; the
+    /// wheel can't change brightness. Return the current slot for black, or
+    /// None if there is no current slot or no slot to choose from.
+    pub fn choose(&mut self, slots: &[WheelSlot], color: &Rgb) -> Option<uint> {
+        let target = match normalize(color) {
+            Some(c) => c,
+            None => return self.current,
+        };
+        let distance = |slot: &WheelSlot| match normalize(&slot.color) {
+            Some(c) => ((c.r - target.r) * (c.r - target.r) + (c.g - target.g) * (c.g - target.g)
+                + (c.b - target.b) * (c.b - target.b)).sqrt(),
+            // A black slot, say a blackout flag, matches no color.
+            None => INFINITY,
+        };
+        let mut best: Option<(uint, f64)> = None;
+        for (i, slot) in slots.iter().enumerate() {
+            if slot.split && !self.allow_splits {
+                continue;
+            }
+            let d = distance(slot);
+            match best {
+                Some((_, bd)) if bd <= d => (),
+                _ => best = Some((i, d)),
+            }
+        }
+        let (best_i, best_d) = match best {
+            Some(b) => b,
+            None => return None,
+        };
+        self.current = match self.current {
+            Some(c) if c < slots.len() && (!slots[c].split || self.allow_splits)
+                && distance(&slots[c]) <= best_d + self.hysteresis => Some(c),
+            _ => Some(best_i),
+        };
+        self.current
+    }
+}
+
+/// Scale a color to full brightness, or None for black.
+fn normalize(c: &Rgb) -> Option<Rgb> {
+    let max = c.r.max(c.g).max(c.b);
+    if max == 0.0 {
+        return None;
+    }
+    Some(Rgb::new(c.r / max, c.g / max, c.b / max))
+}
+
+/// Set a fixture's color attributes to color, in whatever colorspace they
+/// use. root_path is the path to the fixture within scene. Return false if
+/// the fixture has no color attributes, or they are in an unsupported
+/// colorspace, or there are the wrong number of them.
+pub fn set_color(scene: &Rc<RefCell<DeviceTree>>, root_path: &[uint], color: &Rgb) -> bool {
+    set_color_with_wheel(scene, root_path, color, &mut WheelChooser::new(0.0, true))
+}
+
+/// Like set_color, but if the fixture's color comes from a color wheel, pick
+/// the slot with wheel.
+pub fn set_color_with_wheel(scene: &Rc<RefCell<DeviceTree>>, root_path: &[uint], color: &Rgb,
+        wheel: &mut WheelChooser) -> bool {
+    let root = match find_device_node(scene, root_path) {
+        Some(r) => r,
+        None => return false,
+    };
+    let (space, paths) = match color_paths(&root) {
+        Some(found) => found,
+        None => return false,
+    };
+
+    if paths.len() == 1 {
+        let mut done = false;
+        with_endpoint(&root, paths.get(0).as_slice(), |e, a| match a.wheel {
+            Some(ref slots) => {
+                match wheel.choose(slots.as_slice(), color) {
+                    Some(i) => e.set_val(Discrete(i as i64)),
+                    None => ()
+                }
+                done = true;
+            },
+            None => ()
+        });
+        if done {
+            return true;
+        }
+    }
+
+    let levels = match emitter_levels(space, color) {
+        Some(l) if l.len() == paths.len() => l,
+        _ => return false,
+    };
+    for (path, &level) in paths.iter().zip(levels.iter()) {
+        with_endpoint(&root, path.as_slice(), |e, a| e.set_val(fit_to_topo(a, level)));
+    }
+    true
+}
+
+/// Set a group of fixtures, of whatever makes, to one color.
+pub struct ColorWash {
+    pub color: Rgb,
+    /// Paths to the fixtures within the layer's scene.
+    pub fixtures: Vec<DeviceTreePath>,
+    wheels: Vec<WheelChooser>, // one per fixture, used only by color wheels
+}
+
+impl ColorWash {
+    /// Fixtures with color wheels choose slots with the given hysteresis,
+    /// and may use split colors if allow_splits. See WheelChooser.
+    pub fn new(color: Rgb, fixtures: Vec<DeviceTreePath>, hysteresis: f64,
+            allow_splits: bool) -> ColorWash {
+        let wheels = range(0, fixtures.len())
+            .map(|_| WheelChooser::new(hysteresis, allow_splits))
+            .collect();
+        ColorWash { color: color, fixtures: fixtures, wheels: wheels }
+    }
+}
+
+impl AnimatorPlugin for ColorWash {
+    fn animate(&mut self, _time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+            _frame: Option<&Rc<RefCell<DeviceTree>>>) {
+        for (path, wheel) in self.fixtures.iter().zip(self.wheels.mut_iter()) {
+            set_color_with_wheel(scene, path.as_slice(), &self.color, wheel);
+        }
+    }
+}
+
+#[test]
+fn test_colorspaces() {
+    use test_dimmer::{all_within, continuous_at, load_branch_profile};
+
+    fn close(a: &[f64], b: &[f64]) -> bool {
+        all_within(a, b, 1e-3)
+    }
+    fn levels(space: EffectSubtype, c: Rgb) -> Vec<f64> {
+        emitter_levels(space, &c).unwrap()
+    }
+
+    assert!(close(levels(ColorspaceRgb, Rgb::from_hsb(1.0 / 3.0, 1.0, 1.0)).as_slice(), [0.0, 1.0, 0.0]));
+    let c = Rgb::new(0.2, 0.4, 0.8);
+    let (h, s, v) = c.to_hsb();
+    assert!(close(levels(ColorspaceRgb, Rgb::from_hsb(h, s, v)).as_slice(), [0.2, 0.4, 0.8]));
+    let (h, s, l) = c.to_hsl();
+    assert!(close(levels(ColorspaceRgb, Rgb::from_hsl(h, s, l)).as_slice(), [0.2, 0.4, 0.8]));
+    // D65 is white.
+    assert!(close(levels(ColorspaceRgb, Rgb::from_xy(0.3127, 0.3290, 1.0)).as_slice(), [1.0, 1.0, 1.0]));
+
+    assert!(close(levels(ColorspaceRgbw, Rgb::new(1.0, 0.5, 0.5)).as_slice(), [0.5, 0.0, 0.0, 0.5]));
+    assert!(close(levels(ColorspaceRgbaw, Rgb::new(1.0, 0.75, 0.25)).as_slice(),
+        [0.0, 0.125, 0.0, 0.75, 0.25]));
+    assert!(close(levels(Colorspace3x, Rgb::new(1.0, 0.0, 1.0)).as_slice(), [0.0, 1.0, 0.0]));
+    assert!(close(levels(ColorspaceI3x, Rgb::new(0.5, 0.0, 0.5)).as_slice(), [0.5, 0.0, 1.0, 0.0]));
+
+    // An RGBW fixture, with its dimmer ignored.
+    let p = load_branch_profile("RGBW par", "Par", [
+        ("Dimmer", "Dim", "Dimmer", "ColorspaceI"),
+        ("Red", "R", "Color", "ColorspaceRgbw"),
+        ("Green", "G", "Color", "ColorspaceRgbw"),
+        ("Blue", "B", "Color", "ColorspaceRgbw"),
+        ("White", "W", "Color", "ColorspaceRgbw")]);
+    let scene = device_subtree_from_profile_subtree(&p.root);
+    assert!(set_color(&scene, [], &Rgb::new(0.25, 1.0, 0.25)));
+    let values: Vec<f64> = range(1u, 5).map(|i| continuous_at(&scene, [i])).collect();
+    assert!(close(values.as_slice(), [0.0, 0.75, 0.0, 0.25]));
+
+    // A color wheel: open, red, a red/blue split, and blue. Fading from red
+    // towards magenta, the wheel holds red until the split is clearly closer.
+    let slot = |r: f64, g: f64, b: f64, split: bool| WheelSlot { color: Rgb::new(r, g, b), split: split };
+    let slots = vec!(slot(1.0, 1.0, 1.0, false), slot(1.0, 0.0, 0.0, false),
+        slot(0.5, 0.0, 0.5, true), slot(0.0, 0.0, 1.0, false));
+    let mut wheel = WheelChooser::new(0.2, true);
+    assert_eq!(wheel.choose(slots.as_slice(), &Rgb::new(1.0, 0.0, 0.0)), Some(1));
+    assert_eq!(wheel.choose(slots.as_slice(), &Rgb::new(1.0, 0.0, 0.55)), Some(1));
+    assert_eq!(wheel.choose(slots.as_slice(), &Rgb::new(1.0, 0.0, 0.9)), Some(2));
+    assert_eq!(wheel.choose(slots.as_slice(), &Rgb::new(0.0, 0.0, 0.0)), Some(2));
+    assert_eq!(WheelChooser::new(0.0, true).choose(slots.as_slice(), &Rgb::new(1.0, 0.0, 0.55)), Some(2));
+    assert_eq!(WheelChooser::new(0.0, false).choose(slots.as_slice(), &Rgb::new(1.0, 0.0, 0.9)), Some(1));
+}
@@ -0,0 +1,395 @@
+//! A cue stack: playback of stored looks, with timed fades.
+//!
+//! Each cue stores values for some of the endpoints of a device tree,
+//! identified by their DeviceTreePaths within the scene the stack plays back
+//! into. The stack tracks: the look of cue n is every value stored in cues 0
+//! through n, the latest value winning, and any endpoint the stack controls
+//! but that none of those cues sets is at its default.
+//!
+//! Going to a cue starts a fade on each endpoint the stack controls, from
+//! whatever the stack holds right now -- which may be partway through an
+//! earlier fade -- to the cue's look. Fades take the shortest way around for
+//! ring attributes, and discrete attributes snap when their fade begins.
+//!
+//! The stack writes the current look to every endpoint it controls on every
+//! frame, so it can play back as a mixer layer (see AnimatorPlugin). Once
+//! released and faded out, it writes nothing, and the layers beneath show
+//! through.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use device::*;
+use mixer::AnimatorPlugin;
+use mixer::Timepoint;
+use profile::*;
+
+#[deriving(Clone,PartialEq,Show)]
+pub struct CueTiming {
+    /// Fade time for values that rise.
+    pub up_ns: u64,
+    /// Fade time for values that fall.
+    pub down_ns: u64,
+    /// Wait this long after the go before fading.
+    pub delay_ns: u64,
+}
+
+impl CueTiming {
+    /// No delay, no fade.
+    pub fn snap() -> CueTiming {
+        CueTiming { up_ns: 0, down_ns: 0, delay_ns: 0 }
+    }
+}
+
+/// What makes a cue go, once the cue before it has gone.
+pub enum CueTrigger {
+    /// An operator's go.
+    ManualGo,
+    /// Go this long after the previous cue went (a follow).
+    FollowAfterGo(u64),
+    /// Go this long after the previous cue's fades completed (an auto-go).
+    FollowAfterComplete(u64),
+}
+
+pub struct CueValue {
+    pub path: DeviceTreePath,
+    pub value: AttributeValue,
+    /// Override the cue's timing for this endpoint.
+    pub timing: Option<CueTiming>,
+}
+
+pub struct Cue {
+    pub name: String,
+    pub values: Vec<CueValue>,
+    pub timing: CueTiming,
+    pub trigger: CueTrigger,
+}
+
+/// A fade on one endpoint. Once complete, it holds its value.
+struct Fade {
+    path: DeviceTreePath,
+    from: Option<AttributeValue>, // None if the endpoint had no value: snap
+    to: AttributeValue,
+    start_ns: u64,
+    duration_ns: u64,
+}
+
+impl Fade {
+    /// The value at time now, or None if the fade is still delayed and there
+    /// is nothing to hold.
+    fn value_at(&self, now: u64, a: &Attribute) -> Option<AttributeValue> {
+        if now < self.start_ns {
+            return self.from; // still delayed: hold the value faded from
+        }
+        match self.from {
+            Some(from) if a.topo.is_continuous() && now < self.start_ns + self.duration_ns => {
+                let alpha = (now - self.start_ns) as f64 / self.duration_ns as f64;
+                Some(a.mix(self.to, from, alpha).unwrap_or(self.to))
+            },
+            // Discrete values snap, as do values with nothing to fade from.
+            _ => Some(self.to)
+        }
+    }
+
+    fn is_complete(&self, now: u64) -> bool {
+        now >= self.start_ns + self.duration_ns
+    }
+}
+
+pub struct CueStack {
+    pub cues: Vec<Cue>,
+    current: Option<uint>,
+    went_ns: u64,
+    complete_ns: u64,
+    /// The time of the last update.
+    now_ns: u64,
+    /// False if the current cue was reached by goto or back, so the cue after
+    /// it mustn't follow on.
+    follow: bool,
+    /// One fade per controlled path, to the current cue's look.
+    fades: Vec<Fade>,
+}
+
+impl CueStack {
+    pub fn new(cues: Vec<Cue>) -> CueStack {
+        CueStack {
+            cues: cues,
+            current: None,
+            went_ns: 0,
+            complete_ns: 0,
+            now_ns: 0,
+            follow: false,
+            fades: Vec::new(),
+        }
+    }
+
+    /// The index of the last cue to go, or None if none has, or the stack was
+    /// released.
+    pub fn current(&self) -> Option<uint> {
+        self.current
+    }
+
+    /// True if any fade had not yet completed at the last update.
+    pub fn is_fading(&self) -> bool {
+        self.fades.iter().any(|f| !f.is_complete(self.now_ns))
+    }
+
+    /// Go to the next cue, and let the cues after it follow on. Return false
+    /// if there isn't one.
+    pub fn go(&mut self, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>) -> bool {
+        let next = match self.current {
+            Some(i) => i + 1,
+            None => 0,
+        };
+        if !self.goto(next, time, scene) {
+            return false;
+        }
+        self.follow = true;
+        true
+    }
+
+    /// Go back to the previous cue, using its timing. Return false if there
+    /// isn't one.
+    pub fn back(&mut self, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>) -> bool {
+        match self.current {
+            Some(i) if i > 0 => self.goto(i - 1, time, scene),
+            _ => false
+        }
+    }
+
+    /// Go to any cue, using its timing. The cue after it won't follow on
+    /// until the next go. Return false if there is no such cue.
+    pub fn goto(&mut self, index: uint, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>) -> bool {
+        if index >= self.cues.len() {
+            return false;
+        }
+        self.start_transition(Some(index), time.scene_ns, None, scene);
+        self.follow = false;
+        true
+    }
+
+    /// Fade every endpoint the stack controls back to its default.
+    pub fn release(&mut self, fade_ns: u64, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>) {
+        let timing = CueTiming { up_ns: fade_ns, down_ns: fade_ns, delay_ns: 0 };
+        self.start_transition(None, time.scene_ns, Some(timing), scene);
+    }
+
+    /// Advance the fades to time, writing the look into scene, and fire any
+    /// follow cues that are due. Call once per frame.
+    pub fn update(&mut self, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>) {
+        let now = time.scene_ns;
+        // A chain of follows may all fire in one frame.
+        loop {
+            let next = match self.current {
+                Some(i) if self.follow && i + 1 < self.cues.len() => i + 1,
+                _ => break
+            };
+            let at = match self.cues.get(next).trigger {
+                ManualGo => break,
+                FollowAfterGo(d) => self.went_ns + d,
+                FollowAfterComplete(d) => self.complete_ns + d,
+            };
+            if now < at {
+                break;
+            }
+            // The follow's fades start from the values at the moment it
+            // fired.
+            self.start_transition(Some(next), at, None, scene);
+        }
+        self.apply_fades(now, scene);
+    }
+
+    /// Every path any cue sets, once each.
+    fn controlled_paths(&self) -> Vec<DeviceTreePath> {
+        let mut paths: Vec<DeviceTreePath> = Vec::new();
+        for cue in self.cues.iter() {
+            for v in cue.values.iter() {
+                if !paths.contains(&v.path) {
+                    paths.push(v.path.clone());
+                }
+            }
+        }
+        paths
+    }
+
+    /// The tracked value of path in cue index, and the timing to fade to it.
+    /// None for the value if no cue up to index sets it.
+    fn tracked(&self, path: &DeviceTreePath, index: uint) -> (Option<AttributeValue>, CueTiming) {
+        let target = self.cues.get(index);
+        let mut value = None;
+        for cue in self.cues.slice_to(index + 1).iter() {
+            for v in cue.values.iter() {
+                if v.path == *path {
+                    value = Some(v.value);
+                }
+            }
+        }
+        let timing = match target.values.iter().find(|v| v.path == *path) {
+            Some(v) => v.timing.clone().unwrap_or(target.timing.clone()),
+            None => target.timing.clone(),
+        };
+        (value, timing)
+    }
+
+    /// Start fading from the current state to cue index, or to the defaults
+    /// if None. The timing comes from the cue unless given.
+    fn start_transition(&mut self, index: Option<uint>, now: u64,
+            timing: Option<CueTiming>, scene: &Rc<RefCell<DeviceTree>>) {
+
+        let mut fades = Vec::new();
+        for path in self.controlled_paths().move_iter() {
+            let (value, t) = match (index, &timing) {
+                (Some(i), &None) => self.tracked(&path, i),
+                (Some(i), &Some(ref t)) => (self.tracked(&path, i).val0(), t.clone()),
+                (None, &Some(ref t)) => (None, t.clone()),
+                (None, &None) => (None, CueTiming::snap()),
+            };
+            let mut fade = None;
+            with_endpoint(scene, path.as_slice(), |_, a| {
+                let to = match value.or(a.default) {
+                    Some(v) => v,
+                    None => return,
+                };
+                // Fade from whatever the stack holds now, or from the default
+                // if it has never set this path.
+                let from = match self.fades.iter().find(|f| f.path == path) {
+                    Some(f) => f.value_at(now, a),
+                    None => a.default,
+                };
+                let rising = match (from, to) {
+                    (Some(Continuous(x)), Continuous(y)) => y >= x,
+                    (Some(Discrete(x)), Discrete(y)) => y >= x,
+                    _ => true
+                };
+                fade = Some(Fade {
+                    path: path.clone(),
+                    from: from,
+                    to: to,
+                    start_ns: now + t.delay_ns,
+                    duration_ns: if rising { t.up_ns } else { t.down_ns },
+                });
+            });
+            match fade {
+                Some(f) => fades.push(f),
+                None => ()
+            }
+        }
+
+        self.complete_ns = now;
+        for f in fades.iter() {
+            if f.start_ns + f.duration_ns > self.complete_ns {
+                self.complete_ns = f.start_ns + f.duration_ns;
+            }
+        }
+        // Every controlled path gets a new fade, superseding the old one.
+        self.fades = fades;
+        self.current = index;
+        self.went_ns = now;
+    }
+
+    /// Write each fade's value at time now. Once the stack is released, drop
+    /// the fades that are done, so that it writes nothing.
+    fn apply_fades(&mut self, now: u64, scene: &Rc<RefCell<DeviceTree>>) {
+        for f in self.fades.iter() {
+            with_endpoint(scene, f.path.as_slice(), |e, a| match f.value_at(now, a) {
+                Some(v) => e.set_val(v),
+                None => ()
+            });
+        }
+        if self.current.is_none() {
+            self.fades.retain(|f| !f.is_complete(now));
+        }
+        self.now_ns = now;
+    }
+}
+
+impl AnimatorPlugin for CueStack {
+    fn animate(&mut self, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+            _frame: Option<&Rc<RefCell<DeviceTree>>>) {
+        self.update(time, scene);
+    }
+}
+
+#[test]
+fn test_cue_stack() {
+    use test_dimmer::{at, continuous_at, dimmer_pair};
+
+    fn cue(values: Vec<CueValue>, timing: CueTiming, trigger: CueTrigger) -> Cue {
+        Cue { name: String::new(), values: values, timing: timing, trigger: trigger }
+    }
+    fn set(i: uint, v: f64, timing: Option<CueTiming>) -> CueValue {
+        CueValue { path: vec!(i), value: Continuous(v), timing: timing }
+    }
+
+    let p = dimmer_pair();
+    let scene = device_subtree_from_profile_subtree(&p.root);
+
+    let fade = CueTiming { up_ns: 100, down_ns: 100, delay_ns: 0 };
+    let delayed = CueTiming { up_ns: 100, down_ns: 100, delay_ns: 50 };
+    let mut stack = CueStack::new(vec!(
+        cue(vec!(set(0, 1.0, None)), fade.clone(), ManualGo),
+        cue(vec!(set(0, 0.0, None), set(1, 1.0, Some(delayed))), fade.clone(), ManualGo),
+        cue(vec!(set(1, 0.5, None)), CueTiming::snap(), FollowAfterComplete(0))
+    ));
+
+    assert!(stack.go(&at(0), &scene));
+    stack.update(&at(50), &scene);
+    assert_eq!(continuous_at(&scene, [0]), 0.5);
+
+    // Go again halfway through cue 0's fade. Dim1 fades down from where it
+    // got to, and Dim2 is delayed.
+    assert!(stack.go(&at(50), &scene));
+    stack.update(&at(100), &scene);
+    assert_eq!((continuous_at(&scene, [0]), continuous_at(&scene, [1])), (0.25, 0.0));
+
+    // Cue 1 completes at 200, and cue 2 follows immediately.
+    stack.update(&at(200), &scene);
+    assert_eq!(stack.current(), Some(2));
+    assert_eq!((continuous_at(&scene, [0]), continuous_at(&scene, [1])), (0.0, 0.5));
+
+    assert!(stack.back(&at(400), &scene));
+    stack.update(&at(500), &scene);
+    assert_eq!(stack.current(), Some(1));
+    assert_eq!(continuous_at(&scene, [1]), 0.75);
+
+    // Cue 1 completes at 550, but having gone back, cue 2 doesn't follow.
+    stack.update(&at(650), &scene);
+    assert_eq!(stack.current(), Some(1));
+    assert_eq!(continuous_at(&scene, [1]), 1.0);
+
+    stack.release(0, &at(700), &scene);
+    stack.update(&at(700), &scene);
+    assert_eq!(continuous_at(&scene, [1]), 0.0);
+    assert_eq!(stack.current(), None);
+}
+
+#[test]
+fn test_cue_layer() {
+    use mixer::{Animator, GeneratorLayer, MixerTree};
+    use test_dimmer::{at, continuous_at, dimmer_pair};
+    use topo::BlendClobber;
+
+    let p = dimmer_pair();
+    let master = device_subtree_from_profile_subtree(&p.root);
+    let mut mixer = MixerTree::new("Master", master.clone());
+
+    let fade = CueTiming { up_ns: 100, down_ns: 100, delay_ns: 0 };
+    let mut stack = CueStack::new(vec!(Cue { name: String::new(),
+        values: vec!(CueValue { path: vec!(0u), value: Continuous(1.0), timing: None }),
+        timing: fade, trigger: ManualGo }));
+    assert!(stack.go(&at(0), &master));
+    let layer = Animator::new("Cues", "CueStack", &master, vec!(),
+        BlendClobber, box stack as Box<AnimatorPlugin>).unwrap();
+    mixer.children.push(Rc::new(RefCell::new(GeneratorLayer(layer))));
+
+    // The look holds after the fade completes, though the mixer clears the
+    // layer and resets the master every frame. Dim2 isn't controlled, so it
+    // stays at its default.
+    mixer.animate(&at(50));
+    assert_eq!(continuous_at(&master, [0]), 0.5);
+    for &t in [100u64, 200, 1000].iter() {
+        mixer.animate(&at(t));
+        assert_eq!(continuous_at(&master, [0]), 1.0);
+        assert_eq!(continuous_at(&master, [1]), 0.0);
+    }
+}
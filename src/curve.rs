@@ -0,0 +1,171 @@
+//! Response curves: reshape continuous values on their way to the wire, so
+//! that, say, a fade on an incandescent dimmer or an LED looks even to the
+//! eye.
+//!
+//! A curve maps [0.0..1.0] onto [0.0..1.0]. It is applied after blending and
+//! just before a value is quantized for DMX (see DmxMap), so everything
+//! upstream works in perceptual terms, and it is inverted when decoding.
+//! Bipolar values are curved symmetrically about zero.
+
+use std::fmt;
+use std::io::File;
+
+use numeric::limit_unipolar_unit_f64;
+
+pub enum ResponseCurve {
+    LinearCurve,
+    /// n squared: the usual compensation for incandescent and LED dimmers.
+    SquareLaw,
+    /// The square root of n.
+    InverseSquareLaw,
+    /// Slow at both ends and fast in the middle (smoothstep).
+    SCurve,
+    /// n to the given power.
+    Gamma(f64),
+    /// Samples of the curve, evenly spaced from 0.0 to 1.0 inclusive and
+    /// linearly interpolated, e.g. measured from an individual unit. Must
+    /// not decrease, or the inverse is meaningless.
+    LookupTable(Vec<f64>),
+}
+
+impl ResponseCurve {
+    /// Curve n. If bipolar, n is in [-1.0..1.0] and its sign is kept.
+    pub fn apply(&self, n: f64, bipolar: bool) -> f64 {
+        symmetric(n, bipolar, |x| self.apply_unipolar(x))
+    }
+
+    /// Uncurve n: the inverse of apply.
+    pub fn invert(&self, n: f64, bipolar: bool) -> f64 {
+        symmetric(n, bipolar, |x| self.invert_unipolar(x))
+    }
+
+    fn apply_unipolar(&self, n: f64) -> f64 {
+        let x = limit_unipolar_unit_f64(n);
+        limit_unipolar_unit_f64(match *self {
+            LinearCurve => x,
+            SquareLaw => x * x,
+            InverseSquareLaw => x.sqrt(),
+            SCurve => x * x * (3.0 - 2.0 * x),
+            Gamma(g) => x.powf(g),
+            LookupTable(ref t) => lookup(t.as_slice(), x),
+        })
+    }
+
+    fn invert_unipolar(&self, n: f64) -> f64 {
+        let y = limit_unipolar_unit_f64(n);
+        limit_unipolar_unit_f64(match *self {
+            LinearCurve => y,
+            SquareLaw => y.sqrt(),
+            InverseSquareLaw => y * y,
+            // The smoothstep's inverse, by the trigonometric solution of its
+            // cubic.
+            SCurve => 0.5 - ((1.0 - 2.0 * y).asin() / 3.0).sin(),
+            Gamma(g) => y.powf(1.0 / g),
+            LookupTable(ref t) => reverse_lookup(t.as_slice(), y),
+        })
+    }
+}
+
+impl fmt::Show for ResponseCurve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LinearCurve => write!(f, "linear"),
+            SquareLaw => write!(f, "square law"),
+            InverseSquareLaw => write!(f, "inverse square law"),
+            SCurve => write!(f, "S-curve"),
+            Gamma(g) => write!(f, "gamma {}", g),
+            LookupTable(ref t) => write!(f, "lookup table of {} points", t.len()),
+        }
+    }
+}
+
+fn symmetric(n: f64, bipolar: bool, f: |f64| -> f64) -> f64 {
+    if bipolar && n < 0.0 {
+        -f(-n)
+    } else {
+        f(n)
+    }
+}
+
+fn lookup(t: &[f64], x: f64) -> f64 {
+    if t.len() < 2 {
+        return if t.is_empty() { x } else { t[0] };
+    }
+    let p = x * (t.len() - 1) as f64;
+    let i = (p.floor() as uint).min(t.len() - 2);
+    t[i] + (p - i as f64) * (t[i + 1] - t[i])
+}
+
+fn reverse_lookup(t: &[f64], y: f64) -> f64 {
+    if t.len() < 2 {
+        return if t.is_empty() { y } else { 0.0 };
+    }
+    let last = (t.len() - 1) as f64;
+    if y <= t[0] {
+        return 0.0;
+    }
+    for i in range(0, t.len() - 1) {
+        if y <= t[i + 1] {
+            let span = t[i + 1] - t[i];
+            let frac = if span == 0.0 { 0.0 } else { (y - t[i]) / span };
+            return (i as f64 + frac) / last;
+        }
+    }
+    1.0
+}
+
+/// Check that a lookup table can be inverted: at least two points, each in
+/// [0.0..1.0], and never decreasing.
+pub fn validate_table(t: &[f64]) -> Result<(), String> {
+    if t.len() < 2 {
+        return Err("a lookup table needs at least two points".to_string());
+    }
+    if t.iter().any(|&v| v < 0.0 || v > 1.0) {
+        return Err("lookup table points must be in [0.0, 1.0]".to_string());
+    }
+    if t.windows(2).any(|w| w[1] < w[0]) {
+        return Err("lookup table points must not decrease".to_string());
+    }
+    Ok(())
+}
+
+/// Read a lookup table from a file of numbers separated by whitespace, e.g.
+/// a curve measured from one dimmer rack.
+pub fn load_curve_file(path: &Path) -> Result<ResponseCurve, String> {
+    let src = match File::open(path).read_to_string() {
+        Ok(s) => s,
+        Err(e) => return Err(format!("could not read {}: {}", path.display(), e)),
+    };
+    let mut t = Vec::new();
+    for word in src.as_slice().words() {
+        match from_str::<f64>(word) {
+            Some(v) => t.push(v),
+            None => return Err(format!("\"{}\" in {} is not a number", word, path.display())),
+        }
+    }
+    try!(validate_table(t.as_slice()));
+    Ok(LookupTable(t))
+}
+
+#[test]
+fn test_curves() {
+    use test_dimmer::close;
+
+    assert!(close(SquareLaw.apply(0.5, false), 0.25));
+    assert!(close(SquareLaw.apply(-0.5, true), -0.25));
+    assert!(close(SCurve.apply(0.25, false), 0.15625));
+    let table = LookupTable(vec!(0.0, 0.1, 0.1, 1.0));
+    assert!(close(table.apply(0.5, false), 0.1));
+    assert!(close(table.apply(5.0 / 6.0, false), 0.55));
+    assert!(close(table.invert(0.1, false), 1.0 / 3.0));
+
+    let curves = vec!(LinearCurve, SquareLaw, InverseSquareLaw, SCurve, Gamma(2.2),
+        LookupTable(vec!(0.0, 0.05, 0.3, 1.0)));
+    for c in curves.iter() {
+        for i in range(0i, 21) {
+            let x = i as f64 / 10.0 - 1.0;
+            assert!(close(c.invert(c.apply(x, true), true), x), "{} at {}", c, x);
+        }
+    }
+    assert!(validate_table([0.0, 0.5, 0.4]).is_err());
+}
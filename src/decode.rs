@@ -0,0 +1,333 @@
+//! Dmx decoding primitives: the inverses of the renderers in render.rs.
+//!
+//! Each decoder reads the channel(s) a renderer would have written and
+//! recovers the attribute value. Range matrices are degenerate (many channel
+//! values may encode the same attribute value), so decoding is many-to-one.
+//! A decoder returns None if a channel value falls outside every range its
+//! matrix declares; DeviceSwitch::decode relies on this to discover which of
+//! its modes is active.
+
+use dmx::DmxAddressOffset;
+use range::BipolarDmxRangeMatrix;
+use range::BooleanDmxRangeMatrix;
+use range::DmxRange;
+use range::SpinDmxRangeMatrix;
+use range::UnipolarDmxRangeMatrix;
+use render::FineChannelFormat;
+
+/// The fraction of the open interval (0.0..1.0) that channel value v stands
+/// for within range r: the center of its bin (see render.rs). Reverse ranges
+/// are accepted. A degenerate one-value range yields 0.5.
+fn fraction_of_range(v: u8, r: &DmxRange) -> f64 {
+    (r.step_of(v) as f64 + 0.5) / r.steps() as f64
+}
+
+/// Inverse of render_dmx_float_unipolar.
+pub fn decode_dmx_float_unipolar(offset: uint, buffer: &[u8]) -> Option<f64> {
+    Some(buffer[offset] as f64 / 255.0)
+}
+
+/// Inverse of render_dmx_float_bipolar.
+pub fn decode_dmx_float_bipolar(offset: uint, buffer: &[u8]) -> Option<f64> {
+    Some(2.0 * buffer[offset] as f64 / 255.0 - 1.0)
+}
+
+/// Inverse of render_dmx_float_bipolar_with_range. The exact rows (min, mid,
+/// max) take precedence over the interpolated rows (neg, pos).
+pub fn decode_dmx_float_bipolar_with_range(range: &BipolarDmxRangeMatrix,
+        offset: uint, buffer: &[u8]) -> Option<f64> {
+
+    let v = buffer[offset];
+    if range.mid.contains(&v) {
+        Some(0.0)
+    } else if range.min.contains(&v) {
+        Some(-1.0)
+    } else if range.max.contains(&v) {
+        Some(1.0)
+    } else if range.neg.contains(&v) {
+        Some(fraction_of_range(v, &range.neg) - 1.0)
+    } else if range.pos.contains(&v) {
+        Some(fraction_of_range(v, &range.pos))
+    } else {
+        None
+    }
+}
+
+/// Inverse of render_dmx_float_unipolar_with_range.
+pub fn decode_dmx_float_unipolar_with_range(range: &UnipolarDmxRangeMatrix,
+        offset: uint, buffer: &[u8]) -> Option<f64> {
+
+    let v = buffer[offset];
+    if range.min.contains(&v) {
+        Some(0.0)
+    } else if range.max.contains(&v) {
+        Some(1.0)
+    } else if range.mid.contains(&v) {
+        Some(fraction_of_range(v, &range.mid))
+    } else {
+        None
+    }
+}
+
+/// Inverse of render_dmx_double_big_endian.
+pub fn decode_dmx_double_big_endian(offset: &DmxAddressOffset, buffer: &[u8])
+        -> Option<f64> {
+    let n = (buffer[offset.nth(0)] as u16 << 8) | buffer[offset.nth(1)] as u16;
+    Some(n as f64 / 65535.0)
+}
+
+/// Inverse of render_dmx_fine_unipolar. Return None if the value lies outside
+/// the format's range.
+pub fn decode_dmx_fine_unipolar(format: &FineChannelFormat,
+        offset: &DmxAddressOffset, buffer: &[u8]) -> Option<f64> {
+
+    let (lo, hi) = format.span();
+    let v = format.read(offset, buffer);
+    if (v < lo && v < hi) || (v > lo && v > hi) {
+        return None;
+    }
+    let steps = format.steps();
+    if steps == 1 {
+        return Some(0.5);
+    }
+    let i = if lo <= v { v - lo } else { lo - v };
+    Some(i as f64 / (steps - 1) as f64)
+}
+
+/// Inverse of render_dmx_fine_bipolar.
+pub fn decode_dmx_fine_bipolar(format: &FineChannelFormat,
+        offset: &DmxAddressOffset, buffer: &[u8]) -> Option<f64> {
+
+    decode_dmx_fine_unipolar(format, offset, buffer).map(|f| 2.0 * f - 1.0)
+}
+
+/// Inverse of render_dmx_int_indexed_with_range. Return the first index whose
+/// range contains the channel value.
+pub fn decode_dmx_int_indexed_with_range(range: &[DmxRange], offset: uint,
+        buffer: &[u8]) -> Option<i64> {
+
+    let v = buffer[offset];
+    range.iter().position(|r| r.contains(&v)).map(|i| i as i64)
+}
+
+/// Inverse of render_dmx_boolean_with_range.
+pub fn decode_dmx_boolean_with_range(range: &BooleanDmxRangeMatrix,
+        offset: uint, buffer: &[u8]) -> Option<bool> {
+
+    let v = buffer[offset];
+    if range.t.contains(&v) {
+        Some(true)
+    } else if range.f.contains(&v) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Inverse of render_dmx_spin_bipolar_2ch_with_range. The first channel is
+/// mode, the second speed.
+pub fn decode_dmx_spin_bipolar_2ch_with_range(range: &SpinDmxRangeMatrix,
+        offset: &DmxAddressOffset, buffer: &[u8]) -> Option<f64> {
+
+    let mode = buffer[offset.nth(0)];
+    let speed = buffer[offset.nth(1)] as f64 / 255.0;
+    if range.stop.contains(&mode) {
+        Some(0.0)
+    } else if range.fwd.contains(&mode) {
+        Some(speed)
+    } else if range.rev.contains(&mode) {
+        Some(-speed)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_decode_bipolar_with_range() {
+    use range::BipolarChannelValueRangeMatrix;
+    use range::Range;
+
+    let range = BipolarChannelValueRangeMatrix {
+        min: Range { min: 0, max: 0 },
+        neg: Range { min: 1, max: 126 },
+        mid: Range { min: 127, max: 128 },
+        pos: Range { min: 129, max: 254 },
+        max: Range { min: 255, max: 255 },
+    };
+    let buffer = [0u8, 1, 128, 254, 255];
+    assert_eq!(decode_dmx_float_bipolar_with_range(&range, 0, buffer), Some(-1.0));
+    // The interpolated rows decode to the centers of their bins.
+    assert_eq!(decode_dmx_float_bipolar_with_range(&range, 1, buffer), Some(0.5 / 126.0 - 1.0));
+    assert_eq!(decode_dmx_float_bipolar_with_range(&range, 2, buffer), Some(0.0));
+    assert_eq!(decode_dmx_float_bipolar_with_range(&range, 3, buffer), Some(125.5 / 126.0));
+    assert_eq!(decode_dmx_float_bipolar_with_range(&range, 4, buffer), Some(1.0));
+}
+
+#[test]
+fn test_decode_fine() {
+    use dmx::DmxAddressOffsetMultiple;
+    use range::Range;
+    use render::{BigEndian, LittleEndian, render_dmx_fine_bipolar};
+
+    let be16 = FineChannelFormat { bytes: 2, order: BigEndian, range: None };
+    let le24 = FineChannelFormat { bytes: 3, order: LittleEndian, range: None };
+    let rev = FineChannelFormat { bytes: 2, order: BigEndian,
+        range: Some(Range { min: 60000, max: 1000 }) };
+    // Coarse at 0, fine at 2, and for 24 bits, ultra fine at 1.
+    let offset = DmxAddressOffsetMultiple(vec!(0, 2, 1));
+    let mut buffer = [0u8, ..3];
+
+    assert_eq!(render_dmx_fine_bipolar(1.0, &be16, &offset, &mut buffer), 65535);
+    assert_eq!(render_dmx_fine_bipolar(0.0, &be16, &offset, &mut buffer), 32768);
+    assert_eq!(buffer.as_slice(), [128u8, 0, 0].as_slice());
+    assert_eq!(render_dmx_fine_bipolar(0.0, &le24, &offset, &mut buffer), 8388608);
+    assert_eq!(buffer.as_slice(), [0u8, 128, 0].as_slice());
+    assert_eq!(render_dmx_fine_bipolar(-1.0, &rev, &offset, &mut buffer), 60000);
+
+    for &f in [-1.0, -0.3, 0.0, 0.7, 1.0].iter() {
+        for format in [&be16, &le24, &rev].iter() {
+            render_dmx_fine_bipolar(f, *format, &offset, &mut buffer);
+            let decoded = decode_dmx_fine_bipolar(*format, &offset, buffer).unwrap();
+            assert!((decoded - f).abs() < 1.0 / 30000.0, "{} decoded as {}", f, decoded);
+        }
+    }
+    buffer = [255u8, 255, 255];
+    assert_eq!(decode_dmx_fine_bipolar(&rev, &offset, buffer), None);
+}
+
+#[test]
+fn test_round_trips() {
+    use dmx::DmxAddressOffsetSingle;
+    use generator::hash_unit;
+    use range::BipolarChannelValueRangeMatrix;
+    use range::BooleanRangeMatrix;
+    use range::Range;
+    use range::SpinRangeMatrix;
+    use range::UnipolarRangeMatrix;
+    use render::*;
+
+    // For each of a reproducible spread of values in [lo..1.0], including the
+    // ends and zero: rendering and decoding lands within tolerance, and
+    // rendering the decoded value writes the same channels again. Then, for
+    // every value of the first channel that decodes, re-rendering decodes the
+    // same.
+    fn check(name: &str, lo: f64, tolerance: f64,
+            render: |f64, &mut [u8]|, decode: |&[u8]| -> Option<f64>) {
+        let mut samples = vec!(lo, 0.0, 1.0);
+        for i in range(0i64, 2000) {
+            samples.push(lo + (hash_unit(7, i) + 1.0) / 2.0 * (1.0 - lo));
+        }
+        for &x in samples.iter() {
+            let mut buffer = [0u8, ..3];
+            render(x, &mut buffer);
+            let d = match decode(buffer) {
+                Some(d) => d,
+                None => fail!("{}: {} rendered as {} did not decode", name, x, buffer.as_slice()),
+            };
+            assert!((d - x).abs() <= tolerance, "{}: {} decoded as {}", name, x, d);
+            let mut again = [0u8, ..3];
+            render(d, &mut again);
+            assert!(again.as_slice() == buffer.as_slice(), "{}: {} rendered as {}, then {}", name, x, buffer.as_slice(), again.as_slice());
+        }
+        for v in range(0u, 256) {
+            let mut buffer = [v as u8, 0, 0];
+            match decode(buffer) {
+                Some(d) => {
+                    render(d, &mut buffer);
+                    assert_eq!(decode(buffer), Some(d));
+                },
+                None => (),
+            }
+        }
+    }
+
+    check("float_unipolar", 0.0, 1.0 / 255.0,
+        |x, b| { render_dmx_float_unipolar(x, 0, b); },
+        |b| decode_dmx_float_unipolar(0, b));
+    check("float_bipolar", -1.0, 2.0 / 255.0,
+        |x, b| { render_dmx_float_bipolar(x, 0, b); },
+        |b| decode_dmx_float_bipolar(0, b));
+
+    let unipolar = vec!(
+        UnipolarRangeMatrix { min: Range { min: 0, max: 0 },
+            mid: Range { min: 1, max: 254 }, max: Range { min: 255, max: 255 } },
+        UnipolarRangeMatrix { min: Range { min: 255, max: 250 },
+            mid: Range { min: 249, max: 6 }, max: Range { min: 5, max: 0 } },
+        UnipolarRangeMatrix { min: Range { min: 10, max: 12 },
+            mid: Range { min: 200, max: 101 }, max: Range { min: 255, max: 250 } });
+    for m in unipolar.iter() {
+        check("float_unipolar_with_range", 0.0, 1.0 / m.mid.steps() as f64,
+            |x, b| { render_dmx_float_unipolar_with_range(x, m, 0, b); },
+            |b| decode_dmx_float_unipolar_with_range(m, 0, b));
+
+        // Every value in the interpolated row covers an equal share of it.
+        let k = m.mid.steps();
+        let mut hits = Vec::from_elem(256, 0u);
+        let mut buffer = [0u8];
+        for j in range(0, 100 * k) {
+            let x = (j as f64 + 0.5) / (100 * k) as f64;
+            *hits.get_mut(render_dmx_float_unipolar_with_range(x, m, 0, &mut buffer) as uint) += 1;
+        }
+        for i in range(0, k) {
+            assert_eq!(*hits.get(m.mid.step(i) as uint), 100);
+        }
+    }
+
+    let bipolar = vec!(
+        BipolarChannelValueRangeMatrix { min: Range { min: 0, max: 0 },
+            neg: Range { min: 1, max: 126 }, mid: Range { min: 127, max: 128 },
+            pos: Range { min: 129, max: 254 }, max: Range { min: 255, max: 255 } },
+        BipolarChannelValueRangeMatrix { min: Range { min: 255, max: 255 },
+            neg: Range { min: 254, max: 129 }, mid: Range { min: 128, max: 127 },
+            pos: Range { min: 126, max: 1 }, max: Range { min: 0, max: 0 } },
+        BipolarChannelValueRangeMatrix { min: Range { min: 20, max: 20 },
+            neg: Range { min: 21, max: 60 }, mid: Range { min: 61, max: 61 },
+            pos: Range { min: 200, max: 62 }, max: Range { min: 201, max: 210 } });
+    for m in bipolar.iter() {
+        let tolerance = 1.0 / m.neg.steps().min(m.pos.steps()) as f64;
+        check("float_bipolar_with_range", -1.0, tolerance,
+            |x, b| { render_dmx_float_bipolar_with_range(x, m, 0, b); },
+            |b| decode_dmx_float_bipolar_with_range(m, 0, b));
+    }
+
+    let offset = DmxAddressOffsetSingle(0);
+    check("double_big_endian", 0.0, 1.0 / 65535.0,
+        |x, b| { render_dmx_double_big_endian(x, &offset, b); },
+        |b| decode_dmx_double_big_endian(&offset, b));
+
+    let formats = vec!(
+        FineChannelFormat { bytes: 2, order: BigEndian, range: None },
+        FineChannelFormat { bytes: 2, order: LittleEndian, range: Some(Range { min: 40000, max: 300 }) },
+        FineChannelFormat { bytes: 3, order: LittleEndian, range: None });
+    for f in formats.iter() {
+        let tolerance = 2.0 / (f.steps() - 1) as f64;
+        check("fine_bipolar", -1.0, tolerance,
+            |x, b| { render_dmx_fine_bipolar(x, f, &offset, b); },
+            |b| decode_dmx_fine_bipolar(f, &offset, b));
+        check("fine_unipolar", 0.0, tolerance,
+            |x, b| { render_dmx_fine_unipolar(x, f, &offset, b); },
+            |b| decode_dmx_fine_unipolar(f, &offset, b));
+    }
+
+    let spin = SpinRangeMatrix { rev: Range { min: 30, max: 20 },
+        stop: Range { min: 0, max: 9 }, fwd: Range { min: 10, max: 19 } };
+    check("spin_bipolar_2ch_with_range", -1.0, 1.0 / 255.0,
+        |x, b| { render_dmx_spin_bipolar_2ch_with_range(x, &spin, &offset, b); },
+        |b| decode_dmx_spin_bipolar_2ch_with_range(&spin, &offset, b));
+
+    // Discrete renderers round-trip every valid value, and out of range
+    // indices revert to 0.
+    let indexed = vec!(Range { min: 0, max: 9 }, Range { min: 40, max: 10 }, Range { min: 41, max: 41 });
+    let mut buffer = [0u8];
+    for i in range(0i64, 3) {
+        render_dmx_int_indexed_with_range(i, indexed.as_slice(), 0, &mut buffer);
+        assert_eq!(decode_dmx_int_indexed_with_range(indexed.as_slice(), 0, buffer), Some(i));
+    }
+    assert_eq!(render_dmx_int_indexed_with_range(3, indexed.as_slice(), 0, &mut buffer), 0);
+    assert_eq!(render_dmx_int_indexed_with_range(-1, indexed.as_slice(), 0, &mut buffer), 0);
+    let boolean = BooleanRangeMatrix { f: Range { min: 255, max: 128 }, t: Range { min: 127, max: 0 } };
+    for &b in [false, true].iter() {
+        render_dmx_boolean_with_range(b, &boolean, 0, &mut buffer);
+        assert_eq!(decode_dmx_boolean_with_range(&boolean, 0, buffer), Some(b));
+    }
+}
@@ -12,9 +12,14 @@
 
 use std::cell::RefCell;
 use std::cell::Cell;
+use std::fmt;
 use std::rc::Rc;
 
+use curve::ResponseCurve;
+use decode::*;
 use dmx::*;
+use opc::OpcAddr;
+use opc::OpcChannel;
 use profile::*;
 use render::*;
 use world::Loc;
@@ -38,10 +43,117 @@ pub enum Addr {
     DmxAddrType(DmxAddr), // TODO universe + address?
     // Midi_addrType,
     // OscAddrType,
-    // OpenPixelControlAddrType,
+    OpcAddrType(OpcAddr),
     // ...
 }
 
+/// Why a node of a device tree could not be rendered.
+#[deriving(Show)]
+pub enum RenderFault {
+    /// The endpoint is bound to a branch or switch, not an Attribute.
+    UnboundEndpoint,
+    /// The endpoint has no value and its attribute has no default.
+    MissingValue,
+    /// The value is Continuous for a discrete topo, or vice versa.
+    MismatchedValue,
+    /// The attribute has no DmxMap.
+    MissingDmxMap,
+    /// The DmxMap addresses a channel beyond the device's slice of the
+    /// universe, or lists too few offsets for its renderer.
+    OffsetOutOfBounds,
+    /// An indexed attribute's value has no corresponding range.
+    IndexOutOfRange(i64),
+    /// A switch has selected a child it doesn't have.
+    SelectionOutOfBounds(uint),
+    /// The patch addresses channels beyond the end of its universe (or OPC
+    /// channel).
+    PatchOutOfBounds,
+    /// Something else was writing to the universe (or OPC channel).
+    UniverseBusy,
+}
+
+/// A fault, identified by the device and the path of nicknames from the
+/// device's root to the faulty node.
+pub struct RenderError {
+    pub device: String,
+    pub path: String,
+    pub fault: RenderFault,
+}
+
+impl fmt::Show for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}: {}", self.device, self.path, self.fault)
+    }
+}
+
+/// What to do about a faulty node while rendering a live show.
+pub enum RenderPolicy {
+    /// Leave the faulty node's channels alone and carry on.
+    SkipFaults,
+    /// Render the faulty endpoint's last good value, if it ever had one, and
+    /// carry on.
+    HoldLastValue,
+    /// Stop rendering at the first fault.
+    AbortOnFault,
+}
+
+/// The path to the node being rendered, as a chain of stack-allocated steps.
+/// The path is only spelled out as a string if something goes wrong.
+pub struct RenderPath<'a> {
+    pub node: Option<&'a Rc<RefCell<ProfileGraph>>>,
+    pub parent: Option<&'a RenderPath<'a>>,
+}
+
+impl<'a> RenderPath<'a> {
+    pub fn root() -> RenderPath<'a> {
+        RenderPath { node: None, parent: None }
+    }
+
+    pub fn to_string(&self) -> String {
+        let mut path = match self.parent {
+            Some(p) => p.to_string(),
+            None => String::new(),
+        };
+        match self.node {
+            Some(n) => {
+                path.push_char('/');
+                path.push_str(n.borrow().nickname());
+            },
+            None => ()
+        }
+        path
+    }
+}
+
+/// Collect the faults tolerated while rendering one device.
+pub struct RenderContext<'a> {
+    pub device: &'a str,
+    pub policy: RenderPolicy,
+    pub faults: Vec<RenderError>,
+}
+
+impl<'a> RenderContext<'a> {
+    pub fn new(device: &'a str, policy: RenderPolicy) -> RenderContext<'a> {
+        RenderContext { device: device, policy: policy, faults: Vec::new() }
+    }
+
+    /// Record a fault at path. Return it as an error if the policy says abort.
+    pub fn fault(&mut self, path: &RenderPath, fault: RenderFault) -> Result<(), RenderError> {
+        let e = RenderError {
+            device: self.device.to_string(),
+            path: path.to_string(),
+            fault: fault,
+        };
+        match self.policy {
+            AbortOnFault => Err(e),
+            _ => {
+                self.faults.push(e);
+                Ok(())
+            }
+        }
+    }
+}
+
 pub struct DeviceEndpoint {
     /// This must be specially a reference to an Attribute, not any old
     /// ProfileGraph, but we haven't figured out how to express the specialized
@@ -50,6 +162,16 @@ pub struct DeviceEndpoint {
 
     /// Required only if rendering is implemented for this attribute.
     pub value: Cell<Option<AttributeValue>>,
+
+    /// The last value rendered successfully, for RenderPolicy HoldLastValue.
+    pub rendered: Cell<Option<AttributeValue>>,
+
+    /// A response curve for this unit alone, e.g. one measured from the
+    /// dimmer it is plugged into. Overrides the DmxMap's curve.
+    pub curve: Option<Rc<ResponseCurve>>,
+
+    /// Carried from frame to frame, if the DmxMap dithers.
+    pub dither: Cell<DitherState>,
 }
 
 impl DeviceEndpoint {
@@ -64,50 +186,86 @@ impl DeviceEndpoint {
         self.value.set(Some(val))
     }
 
-    /// Fail if you have corrupted the device tree by linking an endpoint to
-    /// a non-Attribute ProfileBranch.
-    pub fn render(&self, buffer: &mut[u8]) {
+    /// Render this endpoint's value (or its attribute's default) into buffer.
+    /// If that's impossible, report the fault to cx and deal with it as its
+    /// policy says.
+    pub fn render(&self, buffer: &mut[u8], path: &RenderPath,
+            cx: &mut RenderContext) -> Result<(), RenderError> {
+
+        match self.render_value(self.get_val(), buffer) {
+            Ok(()) => Ok(()),
+            Err(fault) => {
+                try!(cx.fault(&RenderPath { node: Some(&self.attribute), parent: Some(path) }, fault));
+                match (cx.policy, self.rendered.get()) {
+                    (HoldLastValue, Some(v)) => {
+                        // The last good value rendered once; if it can't now
+                        // (e.g. it was the offsets that broke), skip it.
+                        // Holding a value mustn't advance the dithering.
+                        let dither = self.dither.get();
+                        let _ = self.render_value(Some(v), buffer);
+                        self.dither.set(dither);
+                    },
+                    _ => ()
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Render val, or the attribute's default if val is None. Check everything
+    /// that could go wrong before writing anything to the buffer.
+    fn render_value(&self, val: Option<AttributeValue>, buffer: &mut[u8]) -> Result<(), RenderFault> {
 
         let at_ref = self.attribute.borrow();
 
         let attribute = match *at_ref {
             ProfileGraphAttribute(ref a) => a,
-            _ => fail!("Every DeviceEndpoint must be bound to a ProfileGraphAttribute, not a ProfileGraphBranch."),
+            _ => return Err(UnboundEndpoint),
         };
 
         // Either return my value, or return default if no value.
-        let n: AttributeValue = match self.get_val() {
+        let n: AttributeValue = match val {
             Some(v) => v,
             None => match attribute.default {
                 Some(d) => d,
-                None => fail!("Every attribute which supports rendering must supply a default value."),
+                None => return Err(MissingValue),
             }
         };
 
         // TODO: also make sure this doesn't copy anything by value
         let dmx: &DmxMap = match attribute.dmx {
             Some(ref x) => x,
-            None => fail!("Every attribute which supports rendering must supply a DmxMap (for now)."),
+            None => return Err(MissingDmxMap),
         };
 
-        let offset: uint = match dmx.offset {
-            // TODO Might just collapse this field into DmxAttributeRenderers.
-            DmxAddressOffsetSingle(i) => i
+        let (nf, ni) = match (n, attribute.topo.is_continuous()) {
+            (Continuous(c), true) => match self.curve_for(dmx) {
+                Some(curve) => (curve.apply(c, attribute.topo.is_bipolar()), 0),
+                None => (c, 0),
+            },
+            (Discrete(d), false) => (0.0, d),
+            _ => return Err(MismatchedValue),
         };
 
-        // TODO support for renderers requiring multiple offsets, such as
-        // a double renderer that does not write to adjacent indices in the
-        // framebuffer.
+        for i in range(0u, dmx.renderer.footprint()) {
+            match dmx.offset.try_nth(i) {
+                Some(o) if o < buffer.len() => (),
+                _ => return Err(OffsetOutOfBounds),
+            }
+        }
 
-        let (nf, ni) = match n {
-            Continuous(c) => (c, 0),
-            Discrete(d) => (0.0, d)
-        };
+        // Single-channel renderers write to the first offset. Multi-channel
+        // renderers interpret the whole DmxAddressOffset themselves.
+        let offset: uint = dmx.offset.nth(0);
 
         // Adapt to the interface of the renderer in question.
         match dmx.renderer {
             DmxFloatRenderer(r) => {
-                r(nf, offset, buffer);
+                let n = match dmx.dither {
+                    Some(mode) => self.dither_value(nf, mode, attribute.topo.is_bipolar()),
+                    None => nf,
+                };
+                r(n, offset, buffer);
             },
             DmxFloatBipolarWithRangeRenderer(r, ref range) => {
                 r(nf, range, offset, buffer);
@@ -116,11 +274,16 @@ impl DeviceEndpoint {
                 r(nf, range, offset, buffer);
             },
             DmxDoubleRenderer(r) => {
-                r(nf, offset, buffer);
+                r(nf, &dmx.offset, buffer);
+            },
+            DmxFineRenderer(r, ref format) => {
+                r(nf, format, &dmx.offset, buffer);
             },
             DmxIntIndexedWithRangeRenderer(r, ref range) => {
-                // TODO: fix render to have explicit lifetime specifier to avoid the copy here:
-                r(ni, range.clone(), offset, buffer);
+                if ni < 0 || ni >= range.len() as i64 {
+                    return Err(IndexOutOfRange(ni));
+                }
+                r(ni, range.as_slice(), offset, buffer);
             },
             DmxBooleanWithRangeRenderer(r, ref range) => {
                 // TODO do away with booleans and use int
@@ -131,9 +294,249 @@ impl DeviceEndpoint {
                 r(bi, range, offset, buffer);
             },
             DmxSpinBipolar2ChWithRangeRenderer(r, ref range) => {
-                r(nf, range, offset, buffer);
+                r(nf, range, &dmx.offset, buffer);
+            },
+        };
+        self.rendered.set(Some(n));
+        Ok(())
+    }
+
+    /// Choose this frame's channel value for n by dithering, and return a
+    /// value that the plain float renderers render to exactly that. A device
+    /// patched more than once advances its dithering once per patch.
+    fn dither_value(&self, n: f64, mode: DitherMode, bipolar: bool) -> f64 {
+        let mut state = self.dither.get();
+        let unipolar = if bipolar { (n + 1.0) / 2.0 } else { n };
+        let q = dither_unipolar_f64_to_u8(unipolar, mode, &mut state) as f64 / 255.0;
+        self.dither.set(state);
+        if bipolar { 2.0 * q - 1.0 } else { q }
+    }
+
+    /// The response curve that applies to this endpoint, if any.
+    fn curve_for<'a>(&'a self, dmx: &'a DmxMap) -> Option<&'a ResponseCurve> {
+        match self.curve {
+            Some(ref c) => Some(&**c),
+            None => dmx.curve.as_ref(),
+        }
+    }
+
+    /// Set this endpoint's value from the channel values in buffer, the
+    /// inverse of render(). Return false, leaving the value alone, if the
+    /// channel values are not valid for this attribute's DmxMap. Attributes
+    /// without a DmxMap have nothing to decode and are always valid.
+    pub fn decode(&self, buffer: &[u8]) -> bool {
+        let at_ref = self.attribute.borrow();
+
+        let attribute = match *at_ref {
+            ProfileGraphAttribute(ref a) => a,
+            _ => return false,
+        };
+
+        let dmx: &DmxMap = match attribute.dmx {
+            Some(ref x) => x,
+            None => return true,
+        };
+
+        for i in range(0u, dmx.renderer.footprint()) {
+            match dmx.offset.try_nth(i) {
+                Some(o) if o < buffer.len() => (),
+                _ => return false,
+            }
+        }
+
+        let offset: uint = dmx.offset.nth(0);
+
+        let decoded: Option<AttributeValue> = match dmx.renderer {
+            DmxFloatRenderer(_) => {
+                // The renderer is opaque, so trust the topo's polarity.
+                let f = if attribute.topo.is_bipolar() {
+                    decode_dmx_float_bipolar(offset, buffer)
+                } else {
+                    decode_dmx_float_unipolar(offset, buffer)
+                };
+                f.map(|f| Continuous(f))
+            },
+            DmxFloatBipolarWithRangeRenderer(_, ref range) => {
+                decode_dmx_float_bipolar_with_range(range, offset, buffer).map(|f| Continuous(f))
+            },
+            DmxFloatUnipolarWithRangeRenderer(_, ref range) => {
+                decode_dmx_float_unipolar_with_range(range, offset, buffer).map(|f| Continuous(f))
+            },
+            DmxDoubleRenderer(_) => {
+                decode_dmx_double_big_endian(&dmx.offset, buffer).map(|f| Continuous(f))
+            },
+            DmxFineRenderer(_, ref format) => {
+                // As for DmxFloatRenderer, trust the topo's polarity.
+                let f = if attribute.topo.is_bipolar() {
+                    decode_dmx_fine_bipolar(format, &dmx.offset, buffer)
+                } else {
+                    decode_dmx_fine_unipolar(format, &dmx.offset, buffer)
+                };
+                f.map(|f| Continuous(f))
+            },
+            DmxIntIndexedWithRangeRenderer(_, ref range) => {
+                decode_dmx_int_indexed_with_range(range.as_slice(), offset, buffer).map(|i| Discrete(i))
+            },
+            DmxBooleanWithRangeRenderer(_, ref range) => {
+                decode_dmx_boolean_with_range(range, offset, buffer).map(|b| Discrete(b as i64))
+            },
+            DmxSpinBipolar2ChWithRangeRenderer(_, ref range) => {
+                decode_dmx_spin_bipolar_2ch_with_range(range, &dmx.offset, buffer).map(|f| Continuous(f))
             },
         };
+
+        match decoded {
+            Some(Continuous(f)) => {
+                self.set_val(Continuous(match self.curve_for(dmx) {
+                    Some(curve) => curve.invert(f, attribute.topo.is_bipolar()),
+                    None => f,
+                }));
+                true
+            },
+            Some(v) => {
+                self.set_val(v);
+                true
+            },
+            None => false
+        }
+    }
+}
+
+/// Render any kind of device node into buffer. See DeviceEndpoint::render.
+pub fn render_subtree(node: &Rc<RefCell<DeviceTree>>, buffer: &mut[u8],
+        path: &RenderPath, cx: &mut RenderContext) -> Result<(), RenderError> {
+    match *node.borrow() {
+        // Rust manual: "Patterns that bind variables default to binding
+        // to a copy or move of the matched value (depending on the
+        // matched value's type). This can be changed to bind to a
+        // reference by using the 'ref' keyword, or to a mutable
+        // reference using 'ref mut'."
+        DeviceTreeEndpoint(ref d) => d.render(buffer, path, cx),
+        DeviceTreeBranch(ref d) => d.render(buffer, path, cx),
+        DeviceTreeSwitch(ref d) => d.render(buffer, path, cx),
+    }
+}
+
+/// Decode any kind of device node from buffer. See DeviceEndpoint::decode.
+pub fn decode_subtree(node: &Rc<RefCell<DeviceTree>>, buffer: &[u8]) -> bool {
+    match *node.borrow_mut() {
+        DeviceTreeEndpoint(ref d) => d.decode(buffer),
+        DeviceTreeBranch(ref d) => d.decode(buffer),
+        DeviceTreeSwitch(ref mut d) => d.decode(buffer),
+    }
+}
+
+/// Identify a node in a device tree by the indices of the children leading to
+/// it from some root. The empty path is the root itself.
+pub type DeviceTreePath = Vec<uint>;
+
+/// Follow path down from root. Return None if it leads nowhere, e.g. because
+/// it passes through an endpoint.
+pub fn find_device_node(root: &Rc<RefCell<DeviceTree>>, path: &[uint])
+        -> Option<Rc<RefCell<DeviceTree>>> {
+    if path.is_empty() {
+        return Some(root.clone());
+    }
+    let child = match *root.borrow() {
+        DeviceTreeBranch(ref b) if path[0] < b.children.len() => b.children.get(path[0]).clone(),
+        DeviceTreeSwitch(ref s) if path[0] < s.children.len() => s.children.get(path[0]).clone(),
+        _ => return None,
+    };
+    find_device_node(&child, path.slice_from(1))
+}
+
+/// Call f with the endpoint at path and its attribute, if there is one.
+pub fn with_endpoint(scene: &Rc<RefCell<DeviceTree>>, path: &[uint],
+        f: |&DeviceEndpoint, &Attribute|) {
+    match find_device_node(scene, path) {
+        Some(node) => match *node.borrow() {
+            DeviceTreeEndpoint(ref e) => match *e.attribute.borrow() {
+                ProfileGraphAttribute(ref a) => f(e, a),
+                _ => ()
+            },
+            _ => ()
+        },
+        None => ()
+    }
+}
+
+/// Make an independent copy of a device subtree, e.g. to give a mixer layer
+/// its own private scene. The copy shares profile nodes with the original,
+/// but not values or switch selections.
+pub fn copy_device_subtree(node: &Rc<RefCell<DeviceTree>>) -> Rc<RefCell<DeviceTree>> {
+    let copy = match *node.borrow() {
+        DeviceTreeEndpoint(ref e) => DeviceTreeEndpoint(DeviceEndpoint {
+            attribute: e.attribute.clone(),
+            value: Cell::new(e.value.get()),
+            rendered: Cell::new(e.rendered.get()),
+            curve: e.curve.clone(),
+            dither: Cell::new(e.dither.get()),
+        }),
+        DeviceTreeBranch(ref b) => DeviceTreeBranch(DeviceBranch {
+            profile_branch: b.profile_branch.clone(),
+            children: b.children.iter().map(|c| copy_device_subtree(c)).collect(),
+        }),
+        DeviceTreeSwitch(ref s) => DeviceTreeSwitch(DeviceSwitch {
+            profile_branch: s.profile_branch.clone(),
+            children: s.children.iter().map(|c| copy_device_subtree(c)).collect(),
+            selection: s.selection,
+            selected: s.selected,
+        }),
+    };
+    Rc::new(RefCell::new(copy))
+}
+
+/// Return every value and switch selection in a device subtree to the
+/// defaults given by its profile.
+pub fn reset_device_subtree(node: &Rc<RefCell<DeviceTree>>) {
+    match *node.borrow_mut() {
+        DeviceTreeEndpoint(ref e) => {
+            match *e.attribute.borrow() {
+                ProfileGraphAttribute(ref a) => e.value.set(a.default),
+                _ => ()
+            }
+        },
+        DeviceTreeBranch(ref b) => {
+            for child in b.children.iter() {
+                reset_device_subtree(child);
+            }
+        },
+        DeviceTreeSwitch(ref mut s) => {
+            match s.profile_branch {
+                Some(ref p) => match *p.borrow() {
+                    ProfileGraphSwitch(ref ps) => {
+                        s.selection = ps.default_selection;
+                        s.selected = true;
+                    },
+                    _ => ()
+                },
+                None => ()
+            }
+            for child in s.children.iter() {
+                reset_device_subtree(child);
+            }
+        },
+    }
+}
+
+/// Clear every value in a device subtree, and mark every switch selection
+/// unwritten, leaving the selections themselves alone. An endpoint with no
+/// value, or a switch whose selection is unwritten, is transparent when
+/// blended (see mixer.rs).
+pub fn clear_device_subtree(node: &Rc<RefCell<DeviceTree>>) {
+    match *node.borrow_mut() {
+        DeviceTreeEndpoint(ref e) => e.value.set(None),
+        DeviceTreeBranch(ref b) => {
+            for child in b.children.iter() {
+                clear_device_subtree(child);
+            }
+        },
+        DeviceTreeSwitch(ref mut s) => {
+            s.selected = false;
+            for child in s.children.iter() {
+                clear_device_subtree(child);
+            }
+        },
     }
 }
 
@@ -152,19 +555,22 @@ pub struct DeviceBranch {
 }
 
 impl DeviceBranch {
-    pub fn render(&self, buffer: &mut[u8]) {
+    pub fn render(&self, buffer: &mut[u8], path: &RenderPath,
+            cx: &mut RenderContext) -> Result<(), RenderError> {
+        let here = RenderPath { node: self.profile_branch.as_ref(), parent: Some(path) };
         for child in self.children.iter() {
-            match *child.borrow() {
-                // Rust manual: "Patterns that bind variables default to binding
-                // to a copy or move of the matched value (depending on the
-                // matched value's type). This can be changed to bind to a
-                // reference by using the 'ref' keyword, or to a mutable
-                // reference using 'ref mut'."
-                DeviceTreeEndpoint(ref d) => d.render(buffer),
-                DeviceTreeBranch(ref d) => d.render(buffer),
-                DeviceTreeSwitch(ref d) => d.render(buffer),
-            };
+            try!(render_subtree(child, buffer, &here, cx));
         }
+        Ok(())
+    }
+
+    /// Decode every child. Return true only if all of them were valid.
+    pub fn decode(&self, buffer: &[u8]) -> bool {
+        let mut valid = true;
+        for child in self.children.iter() {
+            valid = decode_subtree(child, buffer) && valid;
+        }
+        valid
     }
 }
 
@@ -184,16 +590,46 @@ pub struct DeviceSwitch {
 
     /// The array index of the selected child branch
     pub selection: uint,
+
+    /// True if the selection was written since the subtree was last cleared.
+    /// Only a written selection blends into the frame beneath.
+    pub selected: bool,
 }
 
 impl DeviceSwitch {
-    pub fn render(&self, buffer: &mut[u8]) {
-        let child = self.children.get(self.selection);
-        match *child.borrow() {
-            DeviceTreeEndpoint(ref d) => d.render(buffer),
-            DeviceTreeBranch(ref d) => d.render(buffer),
-            DeviceTreeSwitch(ref d) => d.render(buffer),
-        };
+    /// Select the child at index, and mark the selection written.
+    pub fn select(&mut self, index: uint) {
+        self.selection = index;
+        self.selected = true;
+    }
+
+    pub fn render(&self, buffer: &mut[u8], path: &RenderPath,
+            cx: &mut RenderContext) -> Result<(), RenderError> {
+        let here = RenderPath { node: self.profile_branch.as_ref(), parent: Some(path) };
+        if self.selection >= self.children.len() {
+            return cx.fault(&here, SelectionOutOfBounds(self.selection));
+        }
+        render_subtree(self.children.get(self.selection), buffer, &here, cx)
+    }
+
+    /// Work out which child is active from the channel values in buffer, select
+    /// it and decode it. A child is active if all of its channels hold values
+    /// valid for it. The current selection is tried first, so that modes
+    /// whose ranges overlap don't flicker; then the others in order. If no
+    /// child is valid, keep the current selection and return false.
+    pub fn decode(&mut self, buffer: &[u8]) -> bool {
+        if self.selection < self.children.len()
+                && decode_subtree(self.children.get(self.selection), buffer) {
+            self.selected = true;
+            return true;
+        }
+        for i in range(0u, self.children.len()) {
+            if i != self.selection && decode_subtree(self.children.get(i), buffer) {
+                self.select(i);
+                return true;
+            }
+        }
+        false
     }
 }
 
@@ -207,8 +643,8 @@ pub struct DevicePatch {
 }
 
 impl DevicePatch {
-    /// Make a new patch in the given universe. Do not (yet) check for
-    /// conflicting patches. (TODO: *optionally* check for conflicts.)
+    /// Make a new patch in the given universe. Do not check for conflicting
+    /// patches; that is the job of patcher::Patcher.
     /// The returned patch has no associated Locs.
     pub fn new_dmx(addr: uint, len: uint, univ: Rc<RefCell<DmxUniverse>> ) -> DevicePatch {
         DevicePatch{
@@ -220,6 +656,19 @@ impl DevicePatch {
             locs: Vec::new()
         }
     }
+
+    /// Make a new patch in the given OPC channel. As with new_dmx, conflicts
+    /// are not checked.
+    pub fn new_opc(addr: uint, len: uint, chan: Rc<RefCell<OpcChannel>>) -> DevicePatch {
+        DevicePatch{
+            addr: OpcAddrType(OpcAddr{
+                channel: chan,
+                address: addr,
+                length: len
+            }),
+            locs: Vec::new()
+        }
+    }
 }
 
 pub struct Device<'p> {
@@ -242,7 +691,10 @@ pub struct Device<'p> {
 }
 
 impl<'p> Device<'p> {
-    pub fn render(&mut self) {
+    /// Render the device into every universe it is patched into. Faults are
+    /// dealt with according to policy: unless it says to abort, rendering
+    /// carries on past faulty nodes and returns the faults it tolerated.
+    pub fn render(&mut self, policy: RenderPolicy) -> Result<Vec<RenderError>, RenderError> {
         // Proposed: Assemble a list of slices, each a view on a universe's dmx
         // framebuffer, each slice aligned with the beginning of the device and
         // only as long as the device, to isolate damage.
@@ -260,6 +712,8 @@ impl<'p> Device<'p> {
         // the whole universe. Hopefully this is okay.
         //
         // TODO do not render redundantly if patched more than once (with the same protocol)?
+        let mut cx = RenderContext::new(self.name.as_slice(), policy);
+        let root_path = RenderPath::root();
         for patch in self.patches.mut_iter() {
             match patch.addr {
                 DmxAddrType(ref mut dmx_addr) => {
@@ -271,22 +725,76 @@ impl<'p> Device<'p> {
                     // the &mut we get from it, so we need to hold it in this scope.
                     match dmx_addr.try_get_univ_ref() {
                         Some(mut u_ref) => {
+                            if dmx_addr.address + dmx_addr.length > u_ref.frame.len() {
+                                try!(cx.fault(&root_path, PatchOutOfBounds));
+                                continue;
+                            }
                             let buffer = dmx_addr.slice_universe(&mut u_ref);
+                            try!(render_subtree(&self.root, buffer, &root_path, &mut cx));
+                        },
+                        // If something else is already writing to the
+                        // universe buffer, we can't get access.
+                        None => try!(cx.fault(&root_path, UniverseBusy))
+                    }
+                },
+                OpcAddrType(ref mut opc_addr) => {
+                    match opc_addr.try_get_channel_ref() {
+                        Some(mut c_ref) => {
+                            if opc_addr.address + opc_addr.length > c_ref.frame.len() {
+                                try!(cx.fault(&root_path, PatchOutOfBounds));
+                                continue;
+                            }
+                            let buffer = opc_addr.slice_channel(&mut c_ref);
+                            try!(render_subtree(&self.root, buffer, &root_path, &mut cx));
+                        },
+                        None => try!(cx.fault(&root_path, UniverseBusy))
+                    }
+                }
+            }
+        }
+        Ok(cx.faults)
+    }
 
-                            match *self.root.borrow() {
-                                DeviceTreeEndpoint(ref d) => d.render(buffer),
-                                DeviceTreeBranch(ref d) => d.render(buffer),
-                                DeviceTreeSwitch(ref d) => d.render(buffer),
-                            };
-
-
+    /// The inverse of render: reconstruct this device's state from the
+    /// current contents of its universe(s), e.g. to mirror a DMX stream
+    /// snooped from another console. Return false if any patch's channels
+    /// held values that are invalid for the profile, ran past the end of its
+    /// universe, or its universe was busy.
+    pub fn decode(&mut self) -> bool {
+        let mut valid = true;
+        for patch in self.patches.iter() {
+            match patch.addr {
+                DmxAddrType(ref dmx_addr) => {
+                    match dmx_addr.universe.try_borrow() {
+                        Some(u_ref) => {
+                            if dmx_addr.address + dmx_addr.length > u_ref.frame.len() {
+                                valid = false;
+                                continue;
+                            }
+                            let buffer = u_ref.frame.slice(dmx_addr.address,
+                                dmx_addr.address + dmx_addr.length);
+                            valid = decode_subtree(&self.root, buffer) && valid;
                         },
-                        None => () // if something else is already writing to
-                        // the universe buffer, we can't get access.  give up.
+                        None => valid = false
+                    }
+                },
+                OpcAddrType(ref opc_addr) => {
+                    match opc_addr.channel.try_borrow() {
+                        Some(c_ref) => {
+                            if opc_addr.address + opc_addr.length > c_ref.frame.len() {
+                                valid = false;
+                                continue;
+                            }
+                            let buffer = c_ref.frame.slice(opc_addr.address,
+                                opc_addr.address + opc_addr.length);
+                            valid = decode_subtree(&self.root, buffer) && valid;
+                        },
+                        None => valid = false
                     }
                 }
             }
         }
+        valid
     }
 }
 
@@ -298,6 +806,9 @@ pub fn device_subtree_from_profile_subtree(root: &Rc<RefCell<ProfileGraph>>) ->
                 attribute: root.clone(),
                 // get the default value from the attribute to initialize
                 value: Cell::new(attr.default),
+                rendered: Cell::new(None),
+                curve: None,
+                dither: Cell::new(DitherState::new()),
             })))
         },
         // If this is a profile branch, recursively construct its subtree.
@@ -318,6 +829,7 @@ pub fn device_subtree_from_profile_subtree(root: &Rc<RefCell<ProfileGraph>>) ->
                 children: pb.children.iter().map(|pb_child|
                 	device_subtree_from_profile_subtree(pb_child)).collect(),
                 selection: pb.default_selection,
+                selected: false,
             })))
         },
     }
@@ -347,3 +859,97 @@ pub fn patch<'p>(profile: &'p Profile, device_tree_root: Rc<RefCell<DeviceBranch
     }
 
 }
+
+/// Like patch, but into a slice of an OPC channel, which unlike a DMX
+/// universe may be of any length.
+pub fn patch_opc<'p>(profile: &'p Profile, device_tree_root: Rc<RefCell<DeviceBranch>>, addr: uint, chan: Rc<RefCell<OpcChannel>> ) -> Option<Device<'p>> {
+    match profile.chan_alloc {
+        DmxChannelCount(len) => {
+            device_tree_root.borrow_mut().children.push( device_subtree_from_profile_subtree(&profile.root) );
+
+            Some(Device {
+                profile: profile,
+                name: profile.name.clone(),
+                nickname: profile.nickname.clone(),
+                id: 0,
+                patches: vec!(DevicePatch::new_opc(addr, len, chan)),
+                root: device_tree_root.borrow().children.last().unwrap().clone()
+            })
+        },
+    }
+}
+
+#[test]
+fn test_render_faults() {
+    use loader::load_profile;
+    use test_dimmer::new_tree_root;
+
+    let p = load_profile(r#"{
+        "name": "Fixture", "nickname": "Fix", "channels": 2,
+        "root": {"branch": "Fixture", "nickname": "Fix", "children": [
+            {"attribute": "Bad", "nickname": "Bad",
+             "effect": ["Dimmer", "ColorspaceI", "Value"],
+             "topo": "continuous_euclidian_unipolar", "default": 1.0,
+             "dmx": {"offset": 5, "renderer": "float_unipolar"}},
+            {"attribute": "Good", "nickname": "Good",
+             "effect": ["Dimmer", "ColorspaceI", "Value"],
+             "topo": "continuous_euclidian_unipolar", "default": 1.0,
+             "dmx": {"offset": 1, "renderer": "float_unipolar"}}
+        ]}
+    }"#).ok().unwrap();
+
+    let univ = Rc::new(RefCell::new(DmxUniverse { id: 0, name: "U1".to_string(), frame: [0, ..512] }));
+    let mut d = patch(&p, new_tree_root(), 0, univ.clone()).unwrap();
+
+    let faults = d.render(SkipFaults).ok().unwrap();
+    assert_eq!(faults.len(), 1);
+    assert_eq!(faults.get(0).path.as_slice(), "/Fix/Bad");
+    assert_eq!(univ.borrow().frame[1], 255);
+    assert!(d.render(AbortOnFault).is_err());
+}
+
+#[test]
+fn test_hold_last_value() {
+    use loader::load_profile;
+    use test_dimmer::new_tree_root;
+
+    let p = load_profile(r#"{
+        "name": "Dimmer", "nickname": "Dim", "channels": 1,
+        "root": {"branch": "Dimmer", "nickname": "Dim", "children": [
+            {"attribute": "Intensity", "nickname": "I",
+             "effect": ["Dimmer", "ColorspaceI", "Value"],
+             "topo": "continuous_euclidian_unipolar", "default": 0.0,
+             "dmx": {"offset": 0, "renderer": "float_unipolar",
+                     "dither": "error_diffusion"}}
+        ]}
+    }"#).ok().unwrap();
+
+    let univ = Rc::new(RefCell::new(DmxUniverse { id: 0, name: "U1".to_string(), frame: [0, ..512] }));
+    let mut d = patch(&p, new_tree_root(), 0, univ.clone()).unwrap();
+    with_endpoint(&d.root, [0], |e, _| e.set_val(Continuous(0.3)));
+    assert_eq!(d.render(HoldLastValue).ok().unwrap().len(), 0);
+    let mut before = DitherState::new();
+    with_endpoint(&d.root, [0], |e, _| before = e.dither.get());
+
+    // A value of the wrong kind faults, and 0.3 is held without advancing
+    // the dithering.
+    with_endpoint(&d.root, [0], |e, _| e.set_val(Discrete(1)));
+    assert_eq!(d.render(HoldLastValue).ok().unwrap().len(), 1);
+    let held = univ.borrow().frame[0];
+    assert!(held == 76 || held == 77);
+    with_endpoint(&d.root, [0], |e, _| assert_eq!(e.dither.get(), before));
+}
+
+#[test]
+fn test_decode_out_of_bounds() {
+    use test_dimmer::{dimmer_pair, new_tree_root};
+
+    // Both patches run one channel past the end of their frames.
+    let p = dimmer_pair();
+    let univ = Rc::new(RefCell::new(DmxUniverse { id: 0, name: "U1".to_string(), frame: [0, ..512] }));
+    let mut d = patch(&p, new_tree_root(), 511, univ.clone()).unwrap();
+    assert!(!d.decode());
+    let chan = Rc::new(RefCell::new(OpcChannel::new(1, "Strip", 3)));
+    let mut d = patch_opc(&p, new_tree_root(), 2, chan).unwrap();
+    assert!(!d.decode());
+}
@@ -1,6 +1,8 @@
 //! For modeling DMX attributes in DMX device profiles.
 //! If you have many of the same device, they will all share the same profile.
 
+use curve::ResponseCurve;
+use render::DitherMode;
 use render::DmxAttributeRenderer;
 
 use std::cell::RefCell;
@@ -11,14 +13,36 @@ use std::rc::Rc;
 /// Normally this value specifies insertion order within the serialized output.
 // REF: We might need to collapse this into the DmxAttributeRenderers.
 pub enum DmxAddressOffset {
-    DmxAddressOffsetSingle(uint), // Might not need anything else.
+    /// A single offset. Renderers that write more than one channel write
+    /// to adjacent channels, starting here.
+    DmxAddressOffsetSingle(uint),
 
-    // Not yet implemented, maybe not necessary: map to scattered, noncontiguous
-    // values in the output buffer, as is often the case with CMYK moving
-    // lights. (This might not be needed if we can achieve the same thing by
-    // composing CMYK as a C attribute + an M attribute + a Y attribute + a
-    // K attribute, each with its own offset.)
-    // DmxAddressOffsetMultiple(~[uint]),
+    /// Map to scattered, noncontiguous values in the output buffer, as is
+    /// often the case with coarse/fine pan at channels 1 and 3, or a rotation
+    /// mode channel several channels away from its speed channel. The nth
+    /// offset receives the renderer's nth byte.
+    DmxAddressOffsetMultiple(Vec<uint>),
+}
+
+impl DmxAddressOffset {
+    /// The offset of a renderer's nth byte within the Device's slice.
+    /// Fail if a DmxAddressOffsetMultiple lists fewer than n + 1 offsets; see
+    /// try_nth.
+    pub fn nth(&self, n: uint) -> uint {
+        match *self {
+            DmxAddressOffsetSingle(i) => i + n,
+            DmxAddressOffsetMultiple(ref v) => *v.get(n),
+        }
+    }
+
+    /// Like nth, but return None instead of failing if there is no nth offset.
+    pub fn try_nth(&self, n: uint) -> Option<uint> {
+        match *self {
+            DmxAddressOffsetSingle(i) => Some(i + n),
+            DmxAddressOffsetMultiple(ref v) if n < v.len() => Some(*v.get(n)),
+            DmxAddressOffsetMultiple(_) => None,
+        }
+    }
 }
 
 /// Specify than an attribute should be rendered with a specific function at
@@ -28,6 +52,11 @@ pub struct DmxMap {
     /// For DMX, this is channel offset with the profile, e.g. pan @ ch3.
     pub offset: DmxAddressOffset,
     pub renderer: DmxAttributeRenderer,
+    /// Reshape continuous values just before rendering them (and after
+    /// decoding them). None is linear. See also DeviceEndpoint.curve.
+    pub curve: Option<ResponseCurve>,
+    /// Dither plain float renderers over time. None truncates.
+    pub dither: Option<DitherMode>,
 }
 
 /// Identify a logical DMX universe. This universe may or may not be mapped to
@@ -59,7 +88,8 @@ impl DmxAddr {
     /// deliberately make no effort in this architectural layer to prevent users
     /// from defining overlapping Devices, because although it would be
     /// unconventional, it may prove necessary in some experimental contexts.
-    /// A higher control layer should warn the user about conflicts/overlaps.
+    /// A higher control layer (see patcher::Patcher) should warn the user about
+    /// conflicts/overlaps.
 
     pub fn slice_universe<'a>(&self, univ_ref: &'a mut RefMut<'a, DmxUniverse>) -> &'a mut [u8] {
 
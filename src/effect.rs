@@ -85,3 +85,75 @@ pub enum EffectSubsubtype {
     Wavelength= 2, // e.g. periodic laminar flow waterspout length
     Duration  = 3, // e.g. mspeed smoothing time
 }
+
+/// Look up an EffectType by its variant name, e.g. "Dimmer".
+pub fn effect_type_by_name(name: &str) -> Option<EffectType> {
+    match name {
+        "Misc" => Some(Misc),
+        "ModeSelect" => Some(ModeSelect),
+        "Color" => Some(Color),
+        "Dimmer" => Some(Dimmer),
+        "Relay" => Some(Relay),
+        "Focus" => Some(Focus),
+        "Zoom" => Some(Zoom),
+        "Iris" => Some(Iris),
+        "Frame" => Some(Frame),
+        "Strobe" => Some(Strobe),
+        "Position" => Some(Position),
+        "Orientation" => Some(Orientation),
+        "Transform" => Some(Transform),
+        "FilterSelect" => Some(FilterSelect),
+        "FilterIntensity" => Some(FilterIntensity),
+        "Raster" => Some(Raster),
+        "Control" => Some(Control),
+        "Smoothing" => Some(Smoothing),
+        _ => None
+    }
+}
+
+/// Look up an EffectSubtype by its variant name, e.g. "ColorspaceRgb".
+pub fn effect_subtype_by_name(name: &str) -> Option<EffectSubtype> {
+    match name {
+        "Other" => Some(Other),
+        "ColorspaceRgb" => Some(ColorspaceRgb),
+        "ColorspaceHsb" => Some(ColorspaceHsb),
+        "ColorspaceRgbi" => Some(ColorspaceRgbi),
+        "ColorspaceRgbw" => Some(ColorspaceRgbw),
+        "ColorspaceRgbaw" => Some(ColorspaceRgbaw),
+        "ColorspaceHsl" => Some(ColorspaceHsl),
+        "Colorspace1x" => Some(Colorspace1x),
+        "Colorspace2x" => Some(Colorspace2x),
+        "Colorspace3x" => Some(Colorspace3x),
+        "Colorspace4x" => Some(Colorspace4x),
+        "Colorspace5x" => Some(Colorspace5x),
+        "ColorspaceI1x" => Some(ColorspaceI1x),
+        "ColorspaceI2x" => Some(ColorspaceI2x),
+        "ColorspaceI3x" => Some(ColorspaceI3x),
+        "ColorspaceI4x" => Some(ColorspaceI4x),
+        "ColorspaceI5x" => Some(ColorspaceI5x),
+        "ColorspaceI" => Some(ColorspaceI),
+        "TransformRotate" => Some(TransformRotate),
+        "TransformScroll" => Some(TransformScroll),
+        "TransformTranslate" => Some(TransformTranslate),
+        "FilterMisc" => Some(FilterMisc),
+        "FilterMultiply" => Some(FilterMultiply),
+        "FilterDistort" => Some(FilterDistort),
+        "FilterSubtract" => Some(FilterSubtract),
+        "FilterAdd" => Some(FilterAdd),
+        "OrientationMirror" => Some(OrientationMirror),
+        "OrientationYoke" => Some(OrientationYoke),
+        "FrameNWSE" => Some(FrameNWSE),
+        _ => None
+    }
+}
+
+/// Look up an EffectSubsubtype by its variant name, e.g. "Frequency".
+pub fn effect_subsubtype_by_name(name: &str) -> Option<EffectSubsubtype> {
+    match name {
+        "Value" => Some(Value),
+        "Frequency" => Some(Frequency),
+        "Wavelength" => Some(Wavelength),
+        "Duration" => Some(Duration),
+        _ => None
+    }
+}
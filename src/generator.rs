@@ -0,0 +1,224 @@
+//! Periodic waveform generators (LFOs) for GeneratorLayers.
+//!
+//! An Lfo is a pure function of scene time, so generators render the same
+//! frames whether a show runs live or offline, and the random waveforms are
+//! seeded hashes of the cycle count rather than a stateful RNG.
+//!
+//! An LfoGenerator drives a group of endpoints from one Lfo, optionally
+//! spreading their phases so that the wave chases across the group.
+
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use device::*;
+use mixer::AnimatorPlugin;
+use mixer::Timepoint;
+use profile::*;
+use topo::BlendAdd;
+
+#[deriving(Clone,PartialEq,Show)]
+pub enum Waveform {
+    Sine,
+    /// Rises from -1 to 1 over the first half of the cycle, then falls.
+    Triangle,
+    /// Rises from -1 to 1 over the cycle, then drops.
+    Saw,
+    /// 1 for the given fraction of the cycle (the duty cycle), then -1.
+    Square(f64),
+    /// A new random level each cycle.
+    SampleAndHold,
+    /// Random levels each cycle, smoothly interpolated.
+    SmoothNoise,
+}
+
+/// A low frequency oscillator, yielding offset + amplitude * wave.
+#[deriving(Clone,Show)]
+pub struct Lfo {
+    pub waveform: Waveform,
+    pub frequency_hz: f64,
+    /// In cycles, so 0.25 is a quarter cycle ahead.
+    pub phase: f64,
+    pub amplitude: f64,
+    pub offset: f64,
+    /// Seeds the random waveforms.
+    pub seed: u64,
+}
+
+impl Lfo {
+    /// The LFO's level at time, with extra_phase cycles added.
+    pub fn sample(&self, time: &Timepoint, extra_phase: f64) -> f64 {
+        let cycles = time.scene_ns as f64 / 1e9 * self.frequency_hz + self.phase + extra_phase;
+        self.offset + self.amplitude * wave(self.waveform, cycles, self.seed)
+    }
+}
+
+/// Evaluate a unit waveform, in [-1.0..1.0], at a position measured in cycles.
+pub fn wave(waveform: Waveform, cycles: f64, seed: u64) -> f64 {
+    let n = cycles.floor();
+    let frac = cycles - n;
+    match waveform {
+        Sine => (2.0 * PI * frac).sin(),
+        Triangle => 1.0 - 4.0 * (frac - 0.5).abs(),
+        Saw => 2.0 * frac - 1.0,
+        Square(duty) => if frac < duty { 1.0 } else { -1.0 },
+        SampleAndHold => hash_unit(seed, n as i64),
+        SmoothNoise => {
+            // Cosine interpolation has zero slope at each cycle's level, so
+            // the joins are smooth.
+            let w = (1.0 - (PI * frac).cos()) / 2.0;
+            let a = hash_unit(seed, n as i64);
+            let b = hash_unit(seed, n as i64 + 1);
+            a + w * (b - a)
+        },
+    }
+}
+
+/// Hash a seed and an integer to 64 well-mixed bits (SplitMix64).
+pub fn hash_u64(seed: u64, n: i64) -> u64 {
+    let mut z = seed + (n as u64) * 0x9E3779B97F4A7C15;
+    z = (z ^ (z >> 30)) * 0xBF58476D1CE4E5B9;
+    z = (z ^ (z >> 27)) * 0x94D049BB133111EB;
+    z ^ (z >> 31)
+}
+
+/// Hash a seed and an integer to a level in [-1.0..1.0].
+pub fn hash_unit(seed: u64, n: i64) -> f64 {
+    (hash_u64(seed, n) >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
+/// Fit a generated level to an attribute's topology: limit it to the range of
+/// a Euclidian attribute, wrap it around a ring, or round it to an index.
+pub fn fit_to_topo(attr: &Attribute, level: f64) -> AttributeValue {
+    // Adding zero with the topology's own blender does the limiting or the
+    // wrapping.
+    let (a, zero) = if attr.topo.is_continuous() {
+        (Continuous(level), Continuous(0.0))
+    } else {
+        (Discrete(level.round() as i64), Discrete(0))
+    };
+    match attr.blend(BlendAdd, a, zero) {
+        // Without a known range, a discrete value is only bounded below.
+        Some(Discrete(n)) if n < 0 => Discrete(0),
+        Some(v) => v,
+        None => a,
+    }
+}
+
+/// Drive a group of endpoints with one Lfo.
+pub struct LfoGenerator {
+    pub lfo: Lfo,
+    /// Paths to the endpoints driven, within the layer's scene, in chase
+    /// order.
+    pub targets: Vec<DeviceTreePath>,
+    /// Spread the targets' phases evenly over this many cycles: 0.0 moves
+    /// them in unison, and 1.0 spaces them evenly around one whole cycle.
+    pub spread: f64,
+}
+
+impl AnimatorPlugin for LfoGenerator {
+    fn animate(&mut self, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+            _frame: Option<&Rc<RefCell<DeviceTree>>>) {
+        let count = self.targets.len();
+        for (i, path) in self.targets.iter().enumerate() {
+            let level = self.lfo.sample(time, self.spread * i as f64 / count as f64);
+            with_endpoint(scene, path.as_slice(), |e, a| e.set_val(fit_to_topo(a, level)));
+        }
+    }
+}
+
+#[test]
+fn test_waveforms() {
+    use test_dimmer::{at, close};
+
+    assert!(close(wave(Sine, 0.25, 0), 1.0));
+    assert!(close(wave(Triangle, 0.5, 0), 1.0));
+    assert!(close(wave(Triangle, 1.0, 0), -1.0));
+    assert!(close(wave(Saw, 0.75, 0), 0.5));
+    assert_eq!(wave(Square(0.25), 0.2, 0), 1.0);
+    assert_eq!(wave(Square(0.25), 0.3, 0), -1.0);
+
+    // Random waveforms are repeatable, bounded, and held within a cycle.
+    for i in range(0i, 100) {
+        let x = i as f64 * 0.37;
+        let v = wave(SmoothNoise, x, 7);
+        assert!(v >= -1.0 && v <= 1.0);
+        assert_eq!(v, wave(SmoothNoise, x, 7));
+    }
+    assert_eq!(wave(SampleAndHold, 3.1, 7), wave(SampleAndHold, 3.9, 7));
+    assert!(close(wave(SmoothNoise, 3.0, 7), wave(SampleAndHold, 3.0, 7)));
+
+    // A 1Hz sine, spread over four targets, a quarter cycle apart.
+    let lfo = Lfo { waveform: Sine, frequency_hz: 1.0, phase: 0.0,
+        amplitude: 0.5, offset: 0.5, seed: 0 };
+    let t = at(0);
+    assert!(close(lfo.sample(&t, 0.25), 1.0));
+    assert!(close(lfo.sample(&t, 0.75), 0.0));
+}
+
+#[test]
+fn test_lfo_generator() {
+    use test_dimmer::{at, close, continuous_at, dimmer_pair};
+
+    // Spread over half a cycle, the two dimmers are a quarter cycle apart:
+    // the first at the sine's peak, limited to full, and the second at 0.5.
+    let p = dimmer_pair();
+    let scene = device_subtree_from_profile_subtree(&p.root);
+    let mut lfo = LfoGenerator {
+        lfo: Lfo { waveform: Sine, frequency_hz: 1.0, phase: 0.25,
+            amplitude: 1.0, offset: 0.5, seed: 0 },
+        targets: vec!(vec!(0u), vec!(1u)),
+        spread: 0.5,
+    };
+    lfo.animate(&at(0), &scene, None);
+    assert_eq!(continuous_at(&scene, [0]), 1.0);
+    assert!(close(continuous_at(&scene, [1]), 0.5));
+}
+
+#[test]
+fn test_fit_to_topo() {
+    use loader::load_profile;
+    use test_dimmer::close;
+
+    let p = load_profile(r#"{
+        "name": "Fixture", "nickname": "Fix", "channels": 1,
+        "root": {"branch": "Fixture", "nickname": "Fix", "children": [
+            {"attribute": "Spin", "nickname": "Spin",
+             "effect": ["Transform", "TransformRotate", "Value"],
+             "topo": "continuous_ring_unipolar", "default": 0.0},
+            {"attribute": "Gobo", "nickname": "Gobo",
+             "effect": ["FilterSelect", "FilterSubtract", "Value"],
+             "topo": "discrete_ring", "default": 0,
+             "dmx": {"offset": 0, "renderer": "int_indexed_with_range",
+                     "range": [[0, 63], [64, 127], [128, 191], [192, 255]]}},
+            {"attribute": "Mode", "nickname": "Mode",
+             "effect": ["ModeSelect", "Other", "Value"],
+             "topo": "discrete_set", "default": 0}
+        ]}
+    }"#).ok().unwrap();
+    let fit = |i: uint, level: f64| match *p.root.borrow() {
+        ProfileGraphBranch(ref b) => match *b.children.get(i).borrow() {
+            ProfileGraphAttribute(ref a) => fit_to_topo(a, level),
+            _ => fail!("expected an attribute")
+        },
+        _ => fail!("expected a branch")
+    };
+
+    // Rings wrap, and discrete levels round to the nearest index.
+    match fit(0, 1.25) {
+        Continuous(x) => assert!(close(x, 0.25)),
+        _ => fail!("expected a continuous value")
+    }
+    match fit(1, 4.6) {
+        Discrete(1) => (),
+        _ => fail!("expected the index to wrap around to 1")
+    }
+    match fit(2, 2.6) {
+        Discrete(3) => (),
+        _ => fail!("expected the level to round to 3")
+    }
+    match fit(2, -1.2) {
+        Discrete(0) => (),
+        _ => fail!("expected a negative level to stop at 0")
+    }
+}
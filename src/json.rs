@@ -0,0 +1,283 @@
+//! A minimal JSON reader that remembers the source line of every value.
+//!
+//! The standard serialize::json parser reports line numbers for syntax errors,
+//! but forgets them once the document is parsed. Profile authors need to know
+//! *where* a well-formed document is semantically wrong (an unknown topo, a
+//! bad range), so we keep our own tiny tree. Objects preserve key order.
+
+use std::fmt;
+
+/// A parsed JSON value, tagged with the line on which it begins (from 1).
+#[deriving(Show,Clone)]
+pub struct Json {
+    pub line: uint,
+    pub value: JsonValue,
+}
+
+#[deriving(Show,Clone)]
+pub enum JsonValue {
+    JsonObject(Vec<(String, Json)>),
+    JsonList(Vec<Json>),
+    JsonString(String),
+    JsonNumber(f64),
+    JsonBool(bool),
+    JsonNull,
+}
+
+/// A syntax error, located by line.
+pub struct JsonError {
+    pub line: uint,
+    pub msg: String,
+}
+
+impl fmt::Show for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Json {
+    /// Look up a key in an object. Return None for missing keys and non-objects.
+    pub fn find<'a>(&'a self, key: &str) -> Option<&'a Json> {
+        match self.value {
+            JsonObject(ref entries) => {
+                for &(ref k, ref v) in entries.iter() {
+                    if k.as_slice() == key {
+                        return Some(v);
+                    }
+                }
+                None
+            },
+            _ => None
+        }
+    }
+
+    pub fn as_str<'a>(&'a self) -> Option<&'a str> {
+        match self.value {
+            JsonString(ref s) => Some(s.as_slice()),
+            _ => None
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.value {
+            JsonNumber(n) => Some(n),
+            _ => None
+        }
+    }
+
+    /// Return the value as an integer, but only if it is a whole number.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.value {
+            JsonNumber(n) if n == n.floor() => Some(n as i64),
+            _ => None
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.value {
+            JsonBool(b) => Some(b),
+            _ => None
+        }
+    }
+
+    pub fn as_list<'a>(&'a self) -> Option<&'a [Json]> {
+        match self.value {
+            JsonList(ref l) => Some(l.as_slice()),
+            _ => None
+        }
+    }
+}
+
+/// Parse a complete JSON document.
+pub fn parse(src: &str) -> Result<Json, JsonError> {
+    let mut p = Parser { chars: src.chars().collect(), pos: 0, line: 1 };
+    let v = try!(p.parse_value());
+    p.skip_whitespace();
+    if p.pos < p.chars.len() {
+        return p.err("trailing characters after document");
+    }
+    Ok(v)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: uint,
+    line: uint,
+}
+
+impl Parser {
+    fn err<T>(&self, msg: &str) -> Result<T, JsonError> {
+        Err(JsonError { line: self.line, msg: msg.to_string() })
+    }
+
+    fn peek(&self) -> Option<char> {
+        if self.pos < self.chars.len() {
+            Some(*self.chars.get(self.pos))
+        } else {
+            None
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        match c {
+            Some('\n') => self.line += 1,
+            _ => ()
+        }
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') | Some('\n') => {
+                    self.next();
+                },
+                _ => break
+            }
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        match self.next() {
+            Some(x) if x == c => Ok(()),
+            _ => self.err(format!("expected '{}'", c).as_slice())
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        let line = self.line;
+        let value = match self.peek() {
+            Some('{') => try!(self.parse_object()),
+            Some('[') => try!(self.parse_list()),
+            Some('"') => JsonString(try!(self.parse_string())),
+            Some('t') => { try!(self.parse_word("true")); JsonBool(true) },
+            Some('f') => { try!(self.parse_word("false")); JsonBool(false) },
+            Some('n') => { try!(self.parse_word("null")); JsonNull },
+            Some(c) if c == '-' || c.is_digit() => try!(self.parse_number()),
+            Some(_) => return self.err("unexpected character"),
+            None => return self.err("unexpected end of document"),
+        };
+        Ok(Json { line: line, value: value })
+    }
+
+    fn parse_word(&mut self, word: &str) -> Result<(), JsonError> {
+        for c in word.chars() {
+            match self.next() {
+                Some(x) if x == c => (),
+                _ => return self.err(format!("expected '{}'", word).as_slice())
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, JsonError> {
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                Some(c) if c.is_digit() || c == '-' || c == '+' || c == '.'
+                        || c == 'e' || c == 'E' => {
+                    s.push_char(c);
+                    self.next();
+                },
+                _ => break
+            }
+        }
+        match from_str::<f64>(s.as_slice()) {
+            Some(n) => Ok(JsonNumber(n)),
+            None => self.err(format!("malformed number '{}'", s).as_slice())
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        try!(self.expect('"'));
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\x08',
+                        Some('f') => '\x0c',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => try!(self.parse_unicode_escape()),
+                        _ => return self.err("invalid escape sequence")
+                    };
+                    s.push_char(c);
+                },
+                Some('\n') => return self.err("unterminated string"),
+                Some(c) => s.push_char(c),
+                None => return self.err("unterminated string"),
+            }
+        }
+    }
+
+    /// Decode the XXXX of a \uXXXX escape. Surrogate pairs are not supported;
+    /// profile names should not need them.
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let mut code: u32 = 0;
+        for _ in range(0u, 4) {
+            match self.next().and_then(|c| c.to_digit(16)) {
+                Some(d) => code = code * 16 + d as u32,
+                None => return self.err("invalid \\u escape")
+            }
+        }
+        match ::std::char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => self.err("invalid \\u escape")
+        }
+    }
+
+    fn parse_list(&mut self) -> Result<JsonValue, JsonError> {
+        try!(self.expect('['));
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.next();
+            return Ok(JsonList(items));
+        }
+        loop {
+            items.push(try!(self.parse_value()));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => (),
+                Some(']') => return Ok(JsonList(items)),
+                _ => return self.err("expected ',' or ']'")
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, JsonError> {
+        try!(self.expect('{'));
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(JsonObject(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = try!(self.parse_string());
+            try!(self.expect(':'));
+            let value = try!(self.parse_value());
+            entries.push((key, value));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => (),
+                Some('}') => return Ok(JsonObject(entries)),
+                _ => return self.err("expected ',' or '}'")
+            }
+        }
+    }
+}
@@ -0,0 +1,735 @@
+//! Load Profiles from declarative JSON documents, so that fixture libraries
+//! can be maintained as data rather than as hand-assembled Rust structs.
+//!
+//! A profile document looks like this:
+//!
+//! {
+//!   "name": "Dimmer", "nickname": "Dim", "manufacturer": "Generic",
+//!   "author": "Chris Macklin", "date": "June 7, 2014", "version": 0,
+//!   "channels": 1,
+//!   "root": {
+//!     "attribute": "Dimmer", "nickname": "Dim",
+//!     "effect": ["Dimmer", "ColorspaceI", "Value"],
+//!     "topo": "continuous_euclidian_unipolar",
+//!     "default": 0.0,
+//!     "dmx": {"offset": 0, "renderer": "float_unipolar"}
+//!   }
+//! }
+//!
+//! Graph nodes are objects keyed by their kind:
+//!
+//!   {"attribute": name, "nickname": .., "effect": [..], "topo": .., "default": .., "dmx": {..}}
+//!   {"branch": name, "nickname": .., "children": [..]}
+//!   {"switch": name, "nickname": .., "default": index, "children": [..]}
+//!   {"use": key}
+//!
+//! A "use" node refers to a named node in the document's top-level "nodes"
+//! object. Every use of the same key yields the same Rc, so shared subgraphs
+//! in the file are shared in the resulting ProfileGraph DAG.
+//!
+//! Defaults are read as Continuous or Discrete according to the attribute's
+//! topo. Range matrices are written as objects of [min, max] pairs, named like
+//! the fields of the matrix types in range.rs, e.g. for a unipolar matrix:
+//! "range": {"min": [0, 0], "mid": [1, 254], "max": [255, 255]}. Indexed
+//! ranges are a list of pairs, one per index. Renderers that write more than
+//! one channel may take "offsets": [coarse, fine] instead of a single "offset"
+//! when their channels are not adjacent.
+//!
+//! A DMX map may reshape continuous values with a response curve:
+//! "curve": "square_law" (or "linear", "inverse_square_law", "s_curve"),
+//! {"gamma": 2.2}, {"table": [0.0, 0.1, ..., 1.0]}, or {"file": path} for a
+//! table of whitespace-separated numbers. The discrete renderers,
+//! "int_indexed_with_range" and "boolean_with_range", can't take one.
+//!
+//! The fine renderers, "fine_unipolar" and "fine_bipolar", spread a value
+//! over "bytes": 2 or 3 channels, most significant first unless
+//! "byte_order": "little". An optional "range": [min, max] gives the whole
+//! channel values of each end, e.g. [65535, 0] for a reversed pan.
+//!
+//! The plain float renderers, "float_unipolar" and "float_bipolar", may
+//! dither over time, to approximate levels between channel steps:
+//! "dither": "error_diffusion" or "ordered". Other renderers can't.
+//!
+//! Color wheels may describe the color at each index, in linear RGB:
+//! "wheel": [{"color": [1, 1, 1]}, {"split": true}, {"color": [1, 0, 0]}]
+//! A split position straddling two slots may leave out its color, which is
+//! then the average of its neighbors'.
+//!
+//! Moving heads may describe their yoke (see aim::YokeGeometry) at the top
+//! level, in degrees:
+//! "yoke": {"pan_range": 540, "tilt_range": 270, "pan_home": 0,
+//!          "tilt_home": 0, "invert_pan": false, "invert_tilt": false}
+//! Only the ranges are required.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::File;
+use std::rc::Rc;
+
+use aim::YokeGeometry;
+use color::Rgb;
+use color::WheelSlot;
+use curve::*;
+use dmx::*;
+use effect::effect_subsubtype_by_name;
+use effect::effect_subtype_by_name;
+use effect::effect_type_by_name;
+use json;
+use json::Json;
+use profile::*;
+use range::*;
+use render::*;
+use topo::Topo;
+use topo::topo_by_name;
+
+/// Describe what went wrong while loading a profile, and where: the path of
+/// nicknames from the root to the offending node, and its line in the source.
+pub struct LoadError {
+    pub path: String,
+    pub line: uint,
+    pub msg: String,
+}
+
+impl fmt::Show for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (line {}): {}", self.path, self.line, self.msg)
+    }
+}
+
+fn error<T>(path: &str, node: &Json, msg: String) -> Result<T, LoadError> {
+    Err(LoadError { path: path.to_string(), line: node.line, msg: msg })
+}
+
+/// Read and load a profile document from disk.
+pub fn load_profile_file(path: &Path) -> Result<Profile, LoadError> {
+    match File::open(path).read_to_string() {
+        Ok(src) => load_profile(src.as_slice()),
+        Err(e) => Err(LoadError {
+            path: "".to_string(),
+            line: 0,
+            msg: format!("could not read {}: {}", path.display(), e),
+        })
+    }
+}
+
+/// Load a profile document from a string.
+pub fn load_profile(src: &str) -> Result<Profile, LoadError> {
+    let doc = match json::parse(src) {
+        Ok(d) => d,
+        Err(e) => return Err(LoadError {
+            path: "".to_string(),
+            line: e.line,
+            msg: e.msg,
+        })
+    };
+
+    let mut loader = Loader {
+        defs: doc.find("nodes"),
+        shared: Vec::new(),
+        in_progress: Vec::new(),
+    };
+
+    let root = match doc.find("root") {
+        Some(r) => try!(loader.node(r, "")),
+        None => return error("", &doc, "missing \"root\" node".to_string()),
+    };
+
+    let channels = try!(req_uint(&doc, "channels", ""));
+
+    Ok(Profile {
+        name: try!(req_str(&doc, "name", "")),
+        nickname: try!(req_str(&doc, "nickname", "")),
+        manufacturer: try!(opt_str(&doc, "manufacturer", "")),
+        author: try!(opt_str(&doc, "author", "")),
+        date: try!(opt_str(&doc, "date", "")),
+        version: match doc.find("version") {
+            Some(v) => match v.as_i64() {
+                Some(i) => i as int,
+                None => return error("", v, "\"version\" must be an integer".to_string()),
+            },
+            None => 0,
+        },
+        chan_alloc: DmxChannelCount(channels),
+        yoke: match doc.find("yoke") {
+            Some(y) => Some(Rc::new(try!(yoke(y, "")))),
+            None => None,
+        },
+        root: root,
+    })
+}
+
+/// Transient state for one load: the document's named nodes, and the ones
+/// we have already built so that repeated uses share a single Rc.
+struct Loader<'a> {
+    defs: Option<&'a Json>,
+    shared: Vec<(String, Rc<RefCell<ProfileGraph>>)>,
+    in_progress: Vec<String>, // named nodes being built, to catch cycles
+}
+
+impl<'a> Loader<'a> {
+    fn node(&mut self, node: &Json, parent: &str) -> Result<Rc<RefCell<ProfileGraph>>, LoadError> {
+        // Name the node by its nickname as early as possible, so errors point
+        // somewhere a profile author will recognize.
+        let path = match node.find("nickname").and_then(|n| n.as_str()) {
+            Some(nick) => format!("{}/{}", parent, nick),
+            None => format!("{}/?", parent),
+        };
+        let path = path.as_slice();
+
+        if node.find("use").is_some() {
+            return self.shared_node(node, parent);
+        }
+
+        let graph = if node.find("attribute").is_some() {
+            ProfileGraphAttribute(try!(attribute(node, path)))
+        } else if node.find("branch").is_some() {
+            ProfileGraphBranch(ProfileBranch {
+                name: try!(req_str(node, "branch", path)),
+                nickname: try!(req_str(node, "nickname", path)),
+                children: try!(self.children(node, path)),
+            })
+        } else if node.find("switch").is_some() {
+            let children = try!(self.children(node, path));
+            let default_selection = match node.find("default") {
+                Some(_) => try!(req_uint(node, "default", path)),
+                None => 0,
+            };
+            if default_selection >= children.len() {
+                return error(path, node, format!(
+                    "default selection {} is out of bounds for {} children",
+                    default_selection, children.len()));
+            }
+            ProfileGraphSwitch(ProfileSwitch {
+                name: try!(req_str(node, "switch", path)),
+                nickname: try!(req_str(node, "nickname", path)),
+                children: children,
+                default_selection: default_selection,
+            })
+        } else {
+            return error(path, node, "expected an \"attribute\", \"branch\", \
+                \"switch\" or \"use\" node".to_string());
+        };
+        Ok(Rc::new(RefCell::new(graph)))
+    }
+
+    fn children(&mut self, node: &Json, path: &str) -> Result<Vec<Rc<RefCell<ProfileGraph>>>, LoadError> {
+        let list = match node.find("children") {
+            Some(c) => match c.as_list() {
+                Some(l) => l,
+                None => return error(path, c, "\"children\" must be a list".to_string()),
+            },
+            None => return error(path, node, "missing \"children\"".to_string()),
+        };
+        let mut children = Vec::with_capacity(list.len());
+        for child in list.iter() {
+            children.push(try!(self.node(child, path)));
+        }
+        Ok(children)
+    }
+
+    /// Resolve a {"use": key} node against the top-level "nodes" object.
+    fn shared_node(&mut self, node: &Json, parent: &str) -> Result<Rc<RefCell<ProfileGraph>>, LoadError> {
+        let key = try!(req_str(node, "use", parent));
+        let path = format!("{}/{}", parent, key);
+        let path = path.as_slice();
+
+        for &(ref k, ref n) in self.shared.iter() {
+            if *k == key {
+                return Ok(n.clone());
+            }
+        }
+        if self.in_progress.contains(&key) {
+            return error(path, node, format!("\"{}\" refers to itself", key));
+        }
+        let def = match self.defs.and_then(|d| d.find(key.as_slice())) {
+            Some(d) => d,
+            None => return error(path, node, format!("no node named \"{}\" in \"nodes\"", key)),
+        };
+
+        self.in_progress.push(key.clone());
+        let built = self.node(def, parent);
+        self.in_progress.pop();
+
+        let built = try!(built);
+        self.shared.push((key, built.clone()));
+        Ok(built)
+    }
+}
+
+fn attribute(node: &Json, path: &str) -> Result<Attribute, LoadError> {
+    let topo_node = match node.find("topo") {
+        Some(t) => t,
+        None => return error(path, node, "missing \"topo\"".to_string()),
+    };
+    let topo: &'static Topo = match topo_node.as_str().and_then(topo_by_name) {
+        Some(t) => t,
+        None => return error(path, topo_node, "unknown topo".to_string()),
+    };
+
+    let default = match node.find("default") {
+        Some(d) if topo.is_continuous() => match d.as_f64() {
+            Some(f) => Some(Continuous(f)),
+            None => return error(path, d, "default must be a number for a continuous topo".to_string()),
+        },
+        Some(d) => match d.as_i64() {
+            Some(i) => Some(Discrete(i)),
+            None => return error(path, d, "default must be an integer for a discrete topo".to_string()),
+        },
+        None => None,
+    };
+
+    let attr = Attribute {
+        name: try!(req_str(node, "attribute", path)),
+        nickname: try!(req_str(node, "nickname", path)),
+        effect: try!(effect(node, path)),
+        topo: topo,
+        default: default,
+        dmx: match node.find("dmx") {
+            Some(d) => Some(try!(dmx_map(d, path))),
+            None => None,
+        },
+        wheel: match node.find("wheel") {
+            Some(w) => Some(try!(wheel(w, path))),
+            None => None,
+        },
+    };
+    match (&attr.wheel, attr.discrete_range()) {
+        (&Some(ref slots), Some(r)) if slots.len() as i64 != r.max + 1 =>
+            return error(path, node, "the wheel needs one slot per indexed range".to_string()),
+        _ => ()
+    }
+    Ok(attr)
+}
+
+fn effect(node: &Json, path: &str) -> Result<(EffectType, EffectSubtype, EffectSubsubtype), LoadError> {
+    let e = match node.find("effect") {
+        Some(e) => e,
+        None => return error(path, node, "missing \"effect\"".to_string()),
+    };
+    let names = match e.as_list() {
+        Some(l) if l.len() == 3 => l,
+        _ => return error(path, e, "\"effect\" must be a list of [type, subtype, subsubtype]".to_string()),
+    };
+    let t = match names[0].as_str().and_then(effect_type_by_name) {
+        Some(t) => t,
+        None => return error(path, &names[0], "unknown effect type".to_string()),
+    };
+    let st = match names[1].as_str().and_then(effect_subtype_by_name) {
+        Some(st) => st,
+        None => return error(path, &names[1], "unknown effect subtype".to_string()),
+    };
+    let sst = match names[2].as_str().and_then(effect_subsubtype_by_name) {
+        Some(sst) => sst,
+        None => return error(path, &names[2], "unknown effect subsubtype".to_string()),
+    };
+    Ok((t, st, sst))
+}
+
+fn dmx_map(node: &Json, path: &str) -> Result<DmxMap, LoadError> {
+    let renderer_name = try!(req_str(node, "renderer", path));
+    let renderer = match renderer_name.as_slice() {
+        "float_unipolar" => DmxFloatRenderer(render_dmx_float_unipolar),
+        "float_bipolar" => DmxFloatRenderer(render_dmx_float_bipolar),
+        "float_unipolar_with_range" => DmxFloatUnipolarWithRangeRenderer(
+            render_dmx_float_unipolar_with_range,
+            try!(unipolar_matrix(try!(req(node, "range", path)), path))),
+        "float_bipolar_with_range" => DmxFloatBipolarWithRangeRenderer(
+            render_dmx_float_bipolar_with_range,
+            try!(bipolar_matrix(try!(req(node, "range", path)), path))),
+        "double_big_endian" => DmxDoubleRenderer(render_dmx_double_big_endian),
+        "fine_unipolar" => DmxFineRenderer(
+            render_dmx_fine_unipolar, try!(fine_format(node, path))),
+        "fine_bipolar" => DmxFineRenderer(
+            render_dmx_fine_bipolar, try!(fine_format(node, path))),
+        "int_indexed_with_range" => DmxIntIndexedWithRangeRenderer(
+            render_dmx_int_indexed_with_range,
+            try!(indexed_ranges(try!(req(node, "range", path)), path))),
+        "boolean_with_range" => DmxBooleanWithRangeRenderer(
+            render_dmx_boolean_with_range,
+            try!(boolean_matrix(try!(req(node, "range", path)), path))),
+        "spin_bipolar_2ch_with_range" => DmxSpinBipolar2ChWithRangeRenderer(
+            render_dmx_spin_bipolar_2ch_with_range,
+            try!(spin_matrix(try!(req(node, "range", path)), path))),
+        _ => return error(path, try!(req(node, "renderer", path)),
+            format!("unknown renderer \"{}\"", renderer_name)),
+    };
+    let dither = match node.find("dither") {
+        Some(d) => {
+            match renderer {
+                DmxFloatRenderer(_) => (),
+                _ => return error(path, d, format!("renderer \"{}\" can't dither", renderer_name)),
+            }
+            match d.as_str() {
+                Some("error_diffusion") => Some(ErrorDiffusion),
+                Some("ordered") => Some(OrderedDither),
+                _ => return error(path, d, "\"dither\" must be \"error_diffusion\" or \"ordered\"".to_string()),
+            }
+        },
+        None => None,
+    };
+    let curve = match node.find("curve") {
+        Some(c) => {
+            match renderer {
+                DmxIntIndexedWithRangeRenderer(..) | DmxBooleanWithRangeRenderer(..) =>
+                    return error(path, c, format!("renderer \"{}\" can't take a curve", renderer_name)),
+                _ => ()
+            }
+            Some(try!(response_curve(c, path)))
+        },
+        None => None,
+    };
+    Ok(DmxMap {
+        offset: try!(dmx_offset(node, path)),
+        renderer: renderer,
+        curve: curve,
+        dither: dither,
+    })
+}
+
+/// Read a response curve: the name of a standard curve, or {"gamma": g},
+/// {"table": [...]} or {"file": path} for a lookup table.
+fn response_curve(node: &Json, path: &str) -> Result<ResponseCurve, LoadError> {
+    match node.as_str() {
+        Some("linear") => return Ok(LinearCurve),
+        Some("square_law") => return Ok(SquareLaw),
+        Some("inverse_square_law") => return Ok(InverseSquareLaw),
+        Some("s_curve") => return Ok(SCurve),
+        Some(name) => return error(path, node, format!("unknown curve \"{}\"", name)),
+        None => ()
+    }
+    match (node.find("gamma"), node.find("table"), node.find("file")) {
+        (Some(g), None, None) => match g.as_f64() {
+            Some(g) if g > 0.0 => Ok(Gamma(g)),
+            _ => error(path, node, "\"gamma\" must be a positive number".to_string()),
+        },
+        (None, Some(t), None) => {
+            let mut table = Vec::new();
+            for v in t.as_list().unwrap_or(&[]).iter() {
+                match v.as_f64() {
+                    Some(f) => table.push(f),
+                    None => return error(path, v, "lookup table points must be numbers".to_string()),
+                }
+            }
+            match validate_table(table.as_slice()) {
+                Ok(()) => Ok(LookupTable(table)),
+                Err(msg) => error(path, t, msg),
+            }
+        },
+        (None, None, Some(f)) => match f.as_str() {
+            Some(file) => match load_curve_file(&Path::new(file)) {
+                Ok(c) => Ok(c),
+                Err(msg) => error(path, f, msg),
+            },
+            None => error(path, f, "\"file\" must be a string".to_string()),
+        },
+        _ => error(path, node, "a curve must be a name, or one of \"gamma\", \"table\" or \"file\"".to_string()),
+    }
+}
+
+/// Read either "offset": n, or "offsets": [n, m, ...] for renderers that
+/// write scattered channels.
+fn dmx_offset(node: &Json, path: &str) -> Result<DmxAddressOffset, LoadError> {
+    match node.find("offsets") {
+        Some(o) => match o.as_list() {
+            Some(list) => {
+                let mut offsets = Vec::with_capacity(list.len());
+                for n in list.iter() {
+                    match n.as_i64() {
+                        Some(i) if i >= 0 => offsets.push(i as uint),
+                        _ => return error(path, n, "offsets must be non-negative integers".to_string()),
+                    }
+                }
+                Ok(DmxAddressOffsetMultiple(offsets))
+            },
+            None => error(path, o, "\"offsets\" must be a list".to_string()),
+        },
+        None => Ok(DmxAddressOffsetSingle(try!(req_uint(node, "offset", path)))),
+    }
+}
+
+/// Read the "bytes", "byte_order" and optional "range" of a fine renderer.
+fn fine_format(node: &Json, path: &str) -> Result<FineChannelFormat, LoadError> {
+    let bytes = try!(req_uint(node, "bytes", path));
+    if bytes != 2 && bytes != 3 {
+        return error(path, try!(req(node, "bytes", path)), "\"bytes\" must be 2 or 3".to_string());
+    }
+    let order = match node.find("byte_order") {
+        Some(o) => match o.as_str() {
+            Some("big") => BigEndian,
+            Some("little") => LittleEndian,
+            _ => return error(path, o, "\"byte_order\" must be \"big\" or \"little\"".to_string()),
+        },
+        None => BigEndian,
+    };
+    let top = (1i64 << (8 * bytes)) - 1;
+    let range = match node.find("range") {
+        Some(r) => match r.as_list() {
+            Some(pair) if pair.len() == 2 => match (pair[0].as_i64(), pair[1].as_i64()) {
+                (Some(a), Some(b)) if a >= 0 && a <= top && b >= 0 && b <= top =>
+                    Some(Range { min: a as u32, max: b as u32 }),
+                _ => return error(path, r, format!("range bounds must be integers in [0, {}]", top)),
+            },
+            _ => return error(path, r, "a range must be a [min, max] pair".to_string()),
+        },
+        None => None,
+    };
+    Ok(FineChannelFormat { bytes: bytes, order: order, range: range })
+}
+
+fn dmx_range(node: &Json, path: &str) -> Result<DmxRange, LoadError> {
+    match node.as_list() {
+        Some(pair) if pair.len() == 2 => {
+            let min = pair[0].as_i64();
+            let max = pair[1].as_i64();
+            match (min, max) {
+                (Some(a), Some(b)) if a >= 0 && a <= 255 && b >= 0 && b <= 255 =>
+                    Ok(Range { min: a as u8, max: b as u8 }),
+                _ => error(path, node, "range bounds must be integers in [0, 255]".to_string()),
+            }
+        },
+        _ => error(path, node, "a range must be a [min, max] pair".to_string()),
+    }
+}
+
+fn matrix_row(node: &Json, key: &str, path: &str) -> Result<DmxRange, LoadError> {
+    dmx_range(try!(req(node, key, path)), path)
+}
+
+fn unipolar_matrix(node: &Json, path: &str) -> Result<UnipolarDmxRangeMatrix, LoadError> {
+    Ok(UnipolarRangeMatrix {
+        min: try!(matrix_row(node, "min", path)),
+        mid: try!(matrix_row(node, "mid", path)),
+        max: try!(matrix_row(node, "max", path)),
+    })
+}
+
+fn bipolar_matrix(node: &Json, path: &str) -> Result<BipolarDmxRangeMatrix, LoadError> {
+    Ok(BipolarChannelValueRangeMatrix {
+        min: try!(matrix_row(node, "min", path)),
+        neg: try!(matrix_row(node, "neg", path)),
+        mid: try!(matrix_row(node, "mid", path)),
+        pos: try!(matrix_row(node, "pos", path)),
+        max: try!(matrix_row(node, "max", path)),
+    })
+}
+
+fn boolean_matrix(node: &Json, path: &str) -> Result<BooleanDmxRangeMatrix, LoadError> {
+    Ok(BooleanRangeMatrix {
+        f: try!(matrix_row(node, "f", path)),
+        t: try!(matrix_row(node, "t", path)),
+    })
+}
+
+fn spin_matrix(node: &Json, path: &str) -> Result<SpinDmxRangeMatrix, LoadError> {
+    Ok(SpinRangeMatrix {
+        rev: try!(matrix_row(node, "rev", path)),
+        stop: try!(matrix_row(node, "stop", path)),
+        fwd: try!(matrix_row(node, "fwd", path)),
+    })
+}
+
+fn indexed_ranges(node: &Json, path: &str) -> Result<Vec<DmxRange>, LoadError> {
+    match node.as_list() {
+        Some(rows) => {
+            let mut ranges = Vec::with_capacity(rows.len());
+            for row in rows.iter() {
+                ranges.push(try!(dmx_range(row, path)));
+            }
+            Ok(ranges)
+        },
+        None => error(path, node, "an indexed range must be a list of [min, max] pairs".to_string()),
+    }
+}
+
+fn wheel(node: &Json, path: &str) -> Result<Vec<WheelSlot>, LoadError> {
+    let entries = match node.as_list() {
+        Some(l) if !l.is_empty() => l,
+        _ => return error(path, node, "\"wheel\" must be a list of slots".to_string()),
+    };
+    let mut colors: Vec<Option<Rgb>> = Vec::with_capacity(entries.len());
+    let mut splits = Vec::with_capacity(entries.len());
+    for entry in entries.iter() {
+        let split = try!(opt_bool(entry, "split", path));
+        colors.push(match entry.find("color") {
+            Some(c) => Some(try!(rgb(c, path))),
+            None if split => None,
+            None => return error(path, entry, "a slot needs a \"color\" unless it is a split".to_string()),
+        });
+        splits.push(split);
+    }
+
+    // A split without a color of its own looks like the average of the slots
+    // either side of it, wrapping around the wheel.
+    let n = colors.len();
+    let mut slots = Vec::with_capacity(n);
+    for i in range(0, n) {
+        let color = match *colors.get(i) {
+            Some(ref c) => c.clone(),
+            None => match (colors.get((i + n - 1) % n), colors.get((i + 1) % n)) {
+                (&Some(ref a), &Some(ref b)) =>
+                    Rgb::new((a.r + b.r) / 2.0, (a.g + b.g) / 2.0, (a.b + b.b) / 2.0),
+                _ => return error(path, &entries[i], "a split needs a color or two colored neighbors".to_string()),
+            },
+        };
+        slots.push(WheelSlot { color: color, split: *splits.get(i) });
+    }
+    Ok(slots)
+}
+
+fn rgb(node: &Json, path: &str) -> Result<Rgb, LoadError> {
+    match node.as_list() {
+        Some(l) if l.len() == 3 => match (l[0].as_f64(), l[1].as_f64(), l[2].as_f64()) {
+            (Some(r), Some(g), Some(b)) => return Ok(Rgb::new(r, g, b)),
+            _ => ()
+        },
+        _ => ()
+    }
+    error(path, node, "a color must be a list of [red, green, blue] levels".to_string())
+}
+
+fn yoke(node: &Json, path: &str) -> Result<YokeGeometry, LoadError> {
+    Ok(YokeGeometry {
+        pan_range_deg: try!(req_f64(node, "pan_range", path)),
+        tilt_range_deg: try!(req_f64(node, "tilt_range", path)),
+        pan_home_deg: try!(opt_f64(node, "pan_home", path)),
+        tilt_home_deg: try!(opt_f64(node, "tilt_home", path)),
+        invert_pan: try!(opt_bool(node, "invert_pan", path)),
+        invert_tilt: try!(opt_bool(node, "invert_tilt", path)),
+    })
+}
+
+// Field accessors that turn a missing or mistyped field into a LoadError.
+
+fn req<'a>(node: &'a Json, key: &str, path: &str) -> Result<&'a Json, LoadError> {
+    match node.find(key) {
+        Some(v) => Ok(v),
+        None => error(path, node, format!("missing \"{}\"", key)),
+    }
+}
+
+fn req_str(node: &Json, key: &str, path: &str) -> Result<String, LoadError> {
+    let v = try!(req(node, key, path));
+    match v.as_str() {
+        Some(s) => Ok(s.to_string()),
+        None => error(path, v, format!("\"{}\" must be a string", key)),
+    }
+}
+
+fn opt_str(node: &Json, key: &str, path: &str) -> Result<String, LoadError> {
+    match node.find(key) {
+        Some(_) => req_str(node, key, path),
+        None => Ok("".to_string()),
+    }
+}
+
+fn req_uint(node: &Json, key: &str, path: &str) -> Result<uint, LoadError> {
+    let v = try!(req(node, key, path));
+    match v.as_i64() {
+        Some(i) if i >= 0 => Ok(i as uint),
+        _ => error(path, v, format!("\"{}\" must be a non-negative integer", key)),
+    }
+}
+
+fn req_f64(node: &Json, key: &str, path: &str) -> Result<f64, LoadError> {
+    let v = try!(req(node, key, path));
+    match v.as_f64() {
+        Some(f) => Ok(f),
+        None => error(path, v, format!("\"{}\" must be a number", key)),
+    }
+}
+
+fn opt_f64(node: &Json, key: &str, path: &str) -> Result<f64, LoadError> {
+    match node.find(key) {
+        Some(_) => req_f64(node, key, path),
+        None => Ok(0.0),
+    }
+}
+
+fn opt_bool(node: &Json, key: &str, path: &str) -> Result<bool, LoadError> {
+    match node.find(key) {
+        Some(v) => match v.as_bool() {
+            Some(b) => Ok(b),
+            None => error(path, v, format!("\"{}\" must be true or false", key)),
+        },
+        None => Ok(false),
+    }
+}
+
+#[test]
+fn test_load_shared_nodes() {
+    let p = load_profile(r#"{
+        "name": "Twin", "nickname": "Twin", "channels": 2,
+        "nodes": {
+            "dim": {"attribute": "Dimmer", "nickname": "Dim",
+                    "effect": ["Dimmer", "ColorspaceI", "Value"],
+                    "topo": "continuous_euclidian_unipolar", "default": 0.0}
+        },
+        "root": {"branch": "Twin", "nickname": "Twin",
+                 "children": [{"use": "dim"}, {"use": "dim"}]}
+    }"#).ok().unwrap();
+
+    match *p.root.borrow() {
+        ProfileGraphBranch(ref b) => {
+            assert_eq!(b.children.len(), 2);
+            // Both uses are the same node.
+            assert!(&**b.children.get(0) as *const _ == &**b.children.get(1) as *const _);
+        },
+        _ => fail!("expected a branch")
+    }
+}
+
+#[test]
+fn test_load_error_location() {
+    let e = load_profile(r#"{
+        "name": "Bad", "nickname": "Bad", "channels": 1,
+        "root": {"branch": "Bad", "nickname": "Bad", "children": [
+            {"attribute": "Dimmer", "nickname": "Dim",
+             "effect": ["Dimmer", "ColorspaceI", "Value"],
+             "topo": "continuous_euclidean_unipolar"}
+        ]}
+    }"#).err().unwrap();
+
+    assert_eq!(e.path.as_slice(), "/Bad/Dim");
+    assert_eq!(e.line, 6);
+}
+
+#[test]
+fn test_load_dither() {
+    fn dimmer(renderer: &str) -> String {
+        let mut json = r#"{
+            "name": "Dimmer", "nickname": "Dim", "channels": 1,
+            "root": {"branch": "Dimmer", "nickname": "Dim", "children": [
+                {"attribute": "Intensity", "nickname": "I",
+                 "effect": ["Dimmer", "ColorspaceI", "Value"],
+                 "topo": "continuous_euclidian_unipolar", "default": 0.0,
+                 "dmx": {"offset": 0, "dither": "ordered",
+                         "range": {"min": [0, 0], "mid": [1, 254], "max": [255, 255]},
+                         "renderer": ""#.to_string();
+        json.push_str(renderer);
+        json.push_str(r#""}}
+            ]}
+        }"#);
+        json
+    }
+
+    assert!(load_profile(dimmer("float_unipolar").as_slice()).is_ok());
+    let e = load_profile(dimmer("float_unipolar_with_range").as_slice()).err().unwrap();
+    assert_eq!(e.path.as_slice(), "/Dim/I");
+    assert_eq!(e.msg.as_slice(), "renderer \"float_unipolar_with_range\" can't dither");
+}
+
+#[test]
+fn test_load_curve() {
+    let e = load_profile(r#"{
+        "name": "Gobo", "nickname": "Gobo", "channels": 1,
+        "root": {"branch": "Gobo", "nickname": "Gobo", "children": [
+            {"attribute": "Gobo", "nickname": "G",
+             "effect": ["FilterSelect", "FilterSubtract", "Value"],
+             "topo": "discrete_set", "default": 0,
+             "dmx": {"offset": 0, "renderer": "int_indexed_with_range",
+                     "range": [[0, 127], [128, 255]], "curve": "square_law"}}
+        ]}
+    }"#).err().unwrap();
+    assert_eq!(e.path.as_slice(), "/Gobo/G");
+    assert_eq!(e.msg.as_slice(), "renderer \"int_indexed_with_range\" can't take a curve");
+}
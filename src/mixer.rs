@@ -1,4 +1,4 @@
-/* Placeholder for mixer and mixdown algorithm
+/* Mixer and mixdown algorithm
 
 In past LD50 versions, mixdown went something like this:
 
@@ -71,7 +71,10 @@ happen differently this time.)
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use device::DeviceTree;
+use device::*;
+use profile::*;
+use topo::BlendClobber;
+use topo::BlendMode;
 
 /// TimePoints are emitted by TimeBases. We should be able to distort the
 /// flow of time at will, so we can perform rendering offline or achieve
@@ -81,18 +84,127 @@ use device::DeviceTree;
 /// cron job to shut down the rig during daylight hours).
 /// We use nanoseconds because that's what's returned by time::precise_time_ns.
 /// These little structs should normally be passed by value.
-/// TODO make copyable
+#[deriving(Clone,PartialEq,Show)]
 pub struct Timepoint {
-    scene_ns: u64,
-    system_ns: u64,
-    frame_ct: u64,
+    pub scene_ns: u64,
+    pub system_ns: u64,
+    pub frame_ct: u64,
 }
 
-/// A Timebase regulates the flow of time in a scene.
-/// TODO: apply distortion envelope(s) to the flow of time.
-/// TODO: decouple timekeeping from system time (but see Timeout docs for exceptions).
+/// How a Timebase advances scene time on each tick.
+#[deriving(Clone,PartialEq,Show)]
+pub enum TimebaseMode {
+    /// Scene time follows the system clock.
+    Realtime,
+
+    /// Each tick advances scene time by this many nanoseconds, however long
+    /// it really took. Use this to render a show offline, faster (or slower)
+    /// than realtime, with deterministic results.
+    FixedStep(u64),
+
+    /// Scene time stands still, though frames still count, so strobes keep
+    /// strobing.
+    Paused,
+}
+
+/// Distort the flow of time: map scene time to a rate, where 1.0 is normal
+/// speed, 0.25 is slow motion, 0.0 freezes time and negative rates run it
+/// backwards. Rates are interpolated linearly between points and held
+/// beyond the first and last.
+pub struct RateEnvelope {
+    /// (scene_ns, rate) pairs, sorted by scene_ns.
+    pub points: Vec<(u64, f64)>,
+}
+
+impl RateEnvelope {
+    pub fn rate_at(&self, scene_ns: u64) -> f64 {
+        if self.points.is_empty() {
+            return 1.0;
+        }
+        let (t0, r0) = *self.points.get(0);
+        if scene_ns <= t0 {
+            return r0;
+        }
+        for w in self.points.as_slice().windows(2) {
+            let ((ta, ra), (tb, rb)) = (w[0], w[1]);
+            if scene_ns < tb {
+                let f = (scene_ns - ta) as f64 / (tb - ta) as f64;
+                return ra + f * (rb - ra);
+            }
+        }
+        let (_, rn) = *self.points.last().unwrap();
+        rn
+    }
+}
+
+/// A Timebase regulates the flow of time in a scene. It never reads the
+/// system clock itself: the caller passes the system time to tick, which
+/// keeps offline renders reproducible.
 pub struct Timebase {
     now: Timepoint,
+    pub mode: TimebaseMode,
+
+    /// A constant rate, multiplied by the envelope's (if any).
+    pub rate: f64,
+    pub envelope: Option<RateEnvelope>,
+
+    last_system_ns: Option<u64>,
+
+    /// Fractions of a nanosecond not yet added to scene time, so that slow
+    /// rates don't round time to a standstill.
+    remainder: f64,
+}
+
+impl Timebase {
+    pub fn new(mode: TimebaseMode) -> Timebase {
+        Timebase {
+            now: Timepoint { scene_ns: 0, system_ns: 0, frame_ct: 0 },
+            mode: mode,
+            rate: 1.0,
+            envelope: None,
+            last_system_ns: None,
+            remainder: 0.0,
+        }
+    }
+
+    /// The Timepoint emitted by the last tick.
+    pub fn now(&self) -> Timepoint {
+        self.now
+    }
+
+    /// Advance to the next frame, given the current system time (e.g. from
+    /// time::precise_time_ns), and return the new Timepoint for the mixer
+    /// to animate. The rate is sampled at the start of the frame.
+    pub fn tick(&mut self, system_ns: u64) -> Timepoint {
+        let elapsed = match (self.mode, self.last_system_ns) {
+            (Realtime, Some(last)) if system_ns > last => system_ns - last,
+            (FixedStep(step), _) => step,
+            _ => 0,
+        };
+        let rate = self.rate * match self.envelope {
+            Some(ref e) => e.rate_at(self.now.scene_ns),
+            None => 1.0,
+        };
+        let advance = elapsed as f64 * rate + self.remainder;
+        let whole = advance.trunc();
+        self.remainder = advance - whole;
+        self.now.scene_ns = if whole < 0.0 && -whole > self.now.scene_ns as f64 {
+            self.remainder = 0.0;
+            0 // time can't run back past the start of the scene
+        } else {
+            (self.now.scene_ns as i64 + whole as i64) as u64
+        };
+        self.now.system_ns = system_ns;
+        self.now.frame_ct += 1;
+        self.last_system_ns = Some(system_ns);
+        self.now
+    }
+
+    /// Jump to a point in scene time, e.g. to rehearse a section of a show.
+    pub fn scrub(&mut self, scene_ns: u64) {
+        self.now.scene_ns = scene_ns;
+        self.remainder = 0.0;
+    }
 }
 
 pub enum Layer {
@@ -108,45 +220,428 @@ pub enum Layer {
     FilterLayer(Animator),
 }
 
+impl Layer {
+    /// Animate this layer and blend its output into the same subtree of
+    /// parent_scene, the developing frame of the mixer that owns it.
+    pub fn mixdown(&mut self, time: &Timepoint, parent_scene: &Rc<RefCell<DeviceTree>>) {
+        match *self {
+            SubmixerLayer(ref mut m) => {
+                clear_device_subtree(&m.scene);
+                m.mix_layers(time);
+                blend_into(&m.scene, parent_scene, m.target.as_slice(), m.blend);
+            },
+            GeneratorLayer(ref mut a) => {
+                a.animate(time, None);
+                blend_into(&a.scene, parent_scene, a.target.as_slice(), a.blend);
+            },
+            FilterLayer(ref mut a) => {
+                match find_device_node(parent_scene, a.target.as_slice()) {
+                    Some(frame) => {
+                        a.animate(time, Some(&frame));
+                        blend_subtree(&a.scene, &frame, a.blend);
+                    },
+                    None => ()
+                }
+            },
+        }
+    }
+}
+
 pub struct MixerTree {
     pub layer_name: String,
 
+    /// For a submixer, the subtree of the parent mixer's scene that this
+    /// mixer works on. Ignored at the root.
+    pub target: DeviceTreePath,
+
+    /// The root mixer's scene is the master device tree, which its owner
+    /// renders. A submixer's is a private copy of its target.
     pub scene: Rc<RefCell<DeviceTree>>,
 
+    /// How a submixer's output blends into its parent. Ignored at the root.
+    pub blend: BlendMode,
+
     // TODOMB: verify use of Rc<RefCell<T> with CM
+    /// Layers in order from the bottom of the stack to the top.
     pub children: Vec<Rc<RefCell<Layer>>>,
 
     // (sub)master fader is just an output filter, albeit maybe a special one
     // what about mute, solo, bump, etc? should we generalize switches? or are they modes on master fader filter that switch around the fader?
     // what about transport signals: play, stop, pause?
-    // where do blend modes go?
     // pub output_filters: Vec<Rc<RefCell<Filter>>>,
 }
 
 impl MixerTree {
-    pub fn animate(time: Timepoint) {
-        // TBD - should have similar or same interface as Animator.animate() below
+    /// Make the root mixer, which mixes down directly into the master scene.
+    pub fn new(layer_name: &str, scene: Rc<RefCell<DeviceTree>>) -> MixerTree {
+        MixerTree {
+            layer_name: layer_name.to_string(),
+            target: Vec::new(),
+            scene: scene,
+            blend: BlendClobber,
+            children: Vec::new(),
+        }
+    }
+
+    /// Make a submixer working on a copy of target within parent_scene.
+    /// Return None if there is no such subtree.
+    pub fn new_submixer(layer_name: &str, parent_scene: &Rc<RefCell<DeviceTree>>,
+            target: DeviceTreePath, blend: BlendMode) -> Option<MixerTree> {
+        find_device_node(parent_scene, target.as_slice()).map(|node| {
+            let scene = copy_device_subtree(&node);
+            clear_device_subtree(&scene);
+            MixerTree {
+                layer_name: layer_name.to_string(),
+                target: target.clone(),
+                scene: scene,
+                blend: blend,
+                children: Vec::new(),
+            }
+        })
+    }
+
+    /// Mix down one frame. The scene is reset to the profile defaults, which
+    /// form the bottom of the stack; then each layer is animated and blended
+    /// in, bottom first. Submixers do the same, except that their scenes start
+    /// out clear, so that only what their layers write blends into the parent.
+    /// Results roll up from the leaves to the root, leaving the root's scene
+    /// ready for Device::render.
+    pub fn animate(&mut self, time: &Timepoint) {
+        reset_device_subtree(&self.scene);
+        self.mix_layers(time);
+    }
+
+    fn mix_layers(&mut self, time: &Timepoint) {
+        // Layers animate one after another. Animating them in parallel, as
+        // the notes above propose, would need device trees that can be sent
+        // between tasks, which Rc<RefCell<..>> trees can't.
+        for layer in self.children.iter() {
+            layer.borrow_mut().mixdown(time, &self.scene);
+        }
     }
 }
 
-/// Interface to a plugin such as Mr. Stroboto or Color Organism. Details TDB.
-/// Animator is a mixer component. It's on our side of the fence. We still need
-/// to figure out how a user plugs into it, both statefully and non-statefully.
+/// Interface to a plugin such as Mr. Stroboto or Color Organism.
+pub trait AnimatorPlugin {
+    /// Write this frame's output into scene, the layer's private copy of its
+    /// target subtree. An endpoint left with no value is transparent: the
+    /// frame beneath shows through. So is a switch's mode, unless the plugin
+    /// sets it with DeviceSwitch::select. A filter also gets frame, the same
+    /// subtree of the developing frame, holding what the layers beneath it
+    /// rendered; a generator gets None.
+    fn animate(&mut self, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+        frame: Option<&Rc<RefCell<DeviceTree>>>);
+}
+
+/// Animator is a mixer component. It's on our side of the fence, wrapping
+/// a plugin, which is on the user's side.
 pub struct Animator {
     pub layer_name: String,
     pub effect_name: String,
-    // TODO - some way to say that an animator layer might only work on a
-    // subtree of the whole scene - maybe a reference to a node in the parent
-    // mixer's tree?
+
+    /// The subtree of the parent mixer's scene that this layer works on.
+    pub target: DeviceTreePath,
+
+    /// The layer's private copy of its target subtree, cleared before each
+    /// frame.
+    pub scene: Rc<RefCell<DeviceTree>>,
+
+    /// How this layer's output blends into the frame beneath it.
+    pub blend: BlendMode,
+
+    pub plugin: Box<AnimatorPlugin>,
 }
 
 impl Animator {
-    pub fn animate(time: Timepoint) {
-        // TBD - takes some kind of DeviceTree (copy?), returns the same
-        // TBD - takes some kind of timing object
+    /// Make a layer working on a copy of target within parent_scene. Return
+    /// None if there is no such subtree.
+    pub fn new(layer_name: &str, effect_name: &str, parent_scene: &Rc<RefCell<DeviceTree>>,
+            target: DeviceTreePath, blend: BlendMode, plugin: Box<AnimatorPlugin>) -> Option<Animator> {
+        let node = match find_device_node(parent_scene, target.as_slice()) {
+            Some(n) => n,
+            None => return None,
+        };
+        let scene = copy_device_subtree(&node);
+        clear_device_subtree(&scene);
+        Some(Animator {
+            layer_name: layer_name.to_string(),
+            effect_name: effect_name.to_string(),
+            target: target,
+            scene: scene,
+            blend: blend,
+            plugin: plugin,
+        })
     }
 
-    pub fn repatch(/* some kind of DeviceTree*/) {
-        // TBD
+    /// Clear the scene and let the plugin write this frame's output into it.
+    pub fn animate(&mut self, time: &Timepoint, frame: Option<&Rc<RefCell<DeviceTree>>>) {
+        clear_device_subtree(&self.scene);
+        self.plugin.animate(time, &self.scene, frame)
     }
+
+    /// Take a fresh copy of the target subtree after the show is repatched.
+    /// The plugin keeps its own state. Return false, leaving the old copy in
+    /// place, if the target no longer exists.
+    pub fn repatch(&mut self, parent_scene: &Rc<RefCell<DeviceTree>>) -> bool {
+        match find_device_node(parent_scene, self.target.as_slice()) {
+            Some(node) => {
+                self.scene = copy_device_subtree(&node);
+                clear_device_subtree(&self.scene);
+                true
+            },
+            None => false
+        }
+    }
+}
+
+/// Blend a layer's scene into the target subtree of its parent's scene.
+fn blend_into(scene: &Rc<RefCell<DeviceTree>>, parent_scene: &Rc<RefCell<DeviceTree>>,
+        target: &[uint], blend: BlendMode) {
+    match find_device_node(parent_scene, target) {
+        Some(node) => blend_subtree(scene, &node, blend),
+        None => ()
+    }
+}
+
+/// Blend top into bottom, node by node. Both are copies of the same subtree,
+/// so their shapes match; any mismatched nodes are skipped.
+fn blend_subtree(top: &Rc<RefCell<DeviceTree>>, bottom: &Rc<RefCell<DeviceTree>>,
+        blend: BlendMode) {
+    match *top.borrow() {
+        DeviceTreeEndpoint(ref t) => match *bottom.borrow() {
+            DeviceTreeEndpoint(ref b) => blend_endpoint(t, b, blend),
+            _ => ()
+        },
+        DeviceTreeBranch(ref t) => match *bottom.borrow() {
+            DeviceTreeBranch(ref b) => {
+                for (tc, bc) in t.children.iter().zip(b.children.iter()) {
+                    blend_subtree(tc, bc, blend);
+                }
+            },
+            _ => ()
+        },
+        DeviceTreeSwitch(ref t) => match *bottom.borrow_mut() {
+            DeviceTreeSwitch(ref mut b) => {
+                // Modes can't be blended, so the upper layer's mode wins,
+                // if it chose one this frame.
+                if t.selected {
+                    b.select(t.selection);
+                }
+                for (tc, bc) in t.children.iter().zip(b.children.iter()) {
+                    blend_subtree(tc, bc, blend);
+                }
+            },
+            _ => ()
+        },
+    }
+}
+
+fn blend_endpoint(top: &DeviceEndpoint, bottom: &DeviceEndpoint, blend: BlendMode) {
+    match (top.get_val(), bottom.get_val()) {
+        (None, _) => (),
+        (Some(t), None) => bottom.set_val(t),
+        (Some(t), Some(b)) => match *top.attribute.borrow() {
+            ProfileGraphAttribute(ref a) => bottom.set_val(a.blend(blend, t, b).unwrap_or(t)),
+            _ => ()
+        },
+    }
+}
+
+#[test]
+fn test_mixdown() {
+    use test_dimmer::{at, continuous_at, dimmer_pair};
+    use topo::BlendMax;
+
+    fn set_all(node: &Rc<RefCell<DeviceTree>>, v: f64) {
+        match *node.borrow() {
+            DeviceTreeEndpoint(ref e) => e.set_val(Continuous(v)),
+            DeviceTreeBranch(ref b) => for c in b.children.iter() { set_all(c, v) },
+            DeviceTreeSwitch(ref s) => for c in s.children.iter() { set_all(c, v) },
+        }
+    }
+
+    struct Constant(f64);
+    impl AnimatorPlugin for Constant {
+        fn animate(&mut self, _time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+                _frame: Option<&Rc<RefCell<DeviceTree>>>) {
+            let Constant(v) = *self;
+            set_all(scene, v);
+        }
+    }
+    struct Invert;
+    impl AnimatorPlugin for Invert {
+        fn animate(&mut self, _time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+                frame: Option<&Rc<RefCell<DeviceTree>>>) {
+            set_all(scene, 1.0 - continuous_at(frame.unwrap(), []));
+        }
+    }
+
+    let p = dimmer_pair();
+
+    let master = device_subtree_from_profile_subtree(&p.root);
+    let mut mixer = MixerTree::new("Master", master.clone());
+
+    let base = Animator::new("Base", "Constant", &master, vec!(),
+        BlendClobber, box Constant(0.25) as Box<AnimatorPlugin>).unwrap();
+    let invert = Animator::new("Invert", "Invert", &master, vec!(1u),
+        BlendClobber, box Invert as Box<AnimatorPlugin>).unwrap();
+    let mut sub = MixerTree::new_submixer("Sub", &master, vec!(), BlendMax).unwrap();
+    let half = Animator::new("Half", "Constant", &sub.scene, vec!(),
+        BlendClobber, box Constant(0.5) as Box<AnimatorPlugin>).unwrap();
+    sub.children.push(Rc::new(RefCell::new(GeneratorLayer(half))));
+
+    mixer.children.push(Rc::new(RefCell::new(GeneratorLayer(base))));
+    mixer.children.push(Rc::new(RefCell::new(FilterLayer(invert))));
+    mixer.children.push(Rc::new(RefCell::new(SubmixerLayer(sub))));
+
+    // 0.25 everywhere, then Dim2 inverted to 0.75, then 0.5 HTP.
+    mixer.animate(&at(0));
+    assert_eq!(continuous_at(&master, [0]), 0.5);
+    assert_eq!(continuous_at(&master, [1]), 0.75);
+}
+
+#[test]
+fn test_partial_layers() {
+    use test_dimmer::{continuous_at, dimmer_pair};
+
+
+    struct Constant(f64);
+    impl AnimatorPlugin for Constant {
+        fn animate(&mut self, _time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+                _frame: Option<&Rc<RefCell<DeviceTree>>>) {
+            let Constant(v) = *self;
+            for i in range(0u, 2) {
+                with_endpoint(scene, [i], |e, _| e.set_val(Continuous(v)));
+            }
+        }
+    }
+    // Write the first dimmer on even frames only.
+    struct Blink;
+    impl AnimatorPlugin for Blink {
+        fn animate(&mut self, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+                _frame: Option<&Rc<RefCell<DeviceTree>>>) {
+            if time.frame_ct % 2 == 0 {
+                with_endpoint(scene, [0], |e, _| e.set_val(Continuous(1.0)));
+            }
+        }
+    }
+
+    let p = dimmer_pair();
+
+    let master = device_subtree_from_profile_subtree(&p.root);
+    let mut mixer = MixerTree::new("Master", master.clone());
+    let base = Animator::new("Base", "Constant", &master, vec!(),
+        BlendClobber, box Constant(0.25) as Box<AnimatorPlugin>).unwrap();
+    let blink = Animator::new("Blink", "Blink", &master, vec!(),
+        BlendClobber, box Blink as Box<AnimatorPlugin>).unwrap();
+    let mut sub = MixerTree::new_submixer("Sub", &master, vec!(), BlendClobber).unwrap();
+    let sub_blink = Animator::new("Blink", "Blink", &sub.scene, vec!(),
+        BlendClobber, box Blink as Box<AnimatorPlugin>).unwrap();
+    sub.children.push(Rc::new(RefCell::new(GeneratorLayer(sub_blink))));
+
+    mixer.children.push(Rc::new(RefCell::new(GeneratorLayer(base))));
+    mixer.children.push(Rc::new(RefCell::new(GeneratorLayer(blink))));
+    mixer.children.push(Rc::new(RefCell::new(SubmixerLayer(sub))));
+
+    // The base layer shows through wherever the layers above write nothing,
+    // and nothing lingers from the frame before.
+    for frame_ct in range(0u64, 3) {
+        mixer.animate(&Timepoint { scene_ns: 0, system_ns: 0, frame_ct: frame_ct });
+        let dim1 = if frame_ct % 2 == 0 { 1.0 } else { 0.25 };
+        assert_eq!(continuous_at(&master, [0]), dim1);
+        assert_eq!(continuous_at(&master, [1]), 0.25);
+    }
+}
+
+#[test]
+fn test_switch_layers() {
+    use loader::load_profile;
+    use test_dimmer::continuous_at;
+
+    let p = load_profile(r#"{
+        "name": "Fixture", "nickname": "Fix", "channels": 2,
+        "root": {"branch": "Fixture", "nickname": "Fix", "children": [
+            {"attribute": "Dimmer", "nickname": "Dim",
+             "effect": ["Dimmer", "ColorspaceI", "Value"],
+             "topo": "continuous_euclidian_unipolar", "default": 0.0,
+             "dmx": {"offset": 0, "renderer": "float_unipolar"}},
+            {"switch": "Mode", "nickname": "Mode", "children": [
+                {"attribute": "A", "nickname": "A",
+                 "effect": ["ModeSelect", "Other", "Value"],
+                 "topo": "continuous_euclidian_unipolar", "default": 0.0,
+                 "dmx": {"offset": 1, "renderer": "float_unipolar"}},
+                {"attribute": "B", "nickname": "B",
+                 "effect": ["ModeSelect", "Other", "Value"],
+                 "topo": "continuous_euclidian_unipolar", "default": 0.0,
+                 "dmx": {"offset": 1, "renderer": "float_unipolar"}}
+            ]}
+        ]}
+    }"#).ok().unwrap();
+
+    // Select mode B on even frames only.
+    struct ModeB;
+    impl AnimatorPlugin for ModeB {
+        fn animate(&mut self, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+                _frame: Option<&Rc<RefCell<DeviceTree>>>) {
+            if time.frame_ct % 2 == 0 {
+                match *find_device_node(scene, [1]).unwrap().borrow_mut() {
+                    DeviceTreeSwitch(ref mut s) => s.select(1),
+                    _ => ()
+                }
+            }
+        }
+    }
+    // Write the dimmer, and nothing else.
+    struct Full;
+    impl AnimatorPlugin for Full {
+        fn animate(&mut self, _time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+                _frame: Option<&Rc<RefCell<DeviceTree>>>) {
+            with_endpoint(scene, [0], |e, _| e.set_val(Continuous(1.0)));
+        }
+    }
+    fn selection(scene: &Rc<RefCell<DeviceTree>>) -> uint {
+        match *find_device_node(scene, [1]).unwrap().borrow() {
+            DeviceTreeSwitch(ref s) => s.selection,
+            _ => fail!("expected the Mode switch")
+        }
+    }
+
+    let master = device_subtree_from_profile_subtree(&p.root);
+    let mut mixer = MixerTree::new("Master", master.clone());
+    let mode = Animator::new("Mode", "ModeB", &master, vec!(),
+        BlendClobber, box ModeB as Box<AnimatorPlugin>).unwrap();
+    let full = Animator::new("Full", "Full", &master, vec!(),
+        BlendClobber, box Full as Box<AnimatorPlugin>).unwrap();
+    mixer.children.push(Rc::new(RefCell::new(GeneratorLayer(mode))));
+    mixer.children.push(Rc::new(RefCell::new(GeneratorLayer(full))));
+
+    // The upper layer leaves the mode to the layer beneath, and when that
+    // writes nothing, the profile default shows through.
+    for frame_ct in range(0u64, 3) {
+        mixer.animate(&Timepoint { scene_ns: 0, system_ns: 0, frame_ct: frame_ct });
+        assert_eq!(selection(&master), if frame_ct % 2 == 0 { 1 } else { 0 });
+        assert_eq!(continuous_at(&master, [0]), 1.0);
+    }
+}
+
+#[test]
+fn test_timebase() {
+    let mut tb = Timebase::new(FixedStep(10));
+    assert_eq!(tb.tick(0).scene_ns, 10);
+    assert_eq!(tb.tick(0).scene_ns, 20);
+
+    tb.scrub(100);
+    tb.mode = Paused;
+    let t = tb.tick(0);
+    assert_eq!((t.scene_ns, t.frame_ct), (100, 3));
+
+    // Slow motion from 100ns on: 10ns steps become 5ns.
+    tb.mode = FixedStep(10);
+    tb.envelope = Some(RateEnvelope { points: vec!((0, 1.0), (100, 0.5)) });
+    assert_eq!(tb.tick(0).scene_ns, 105);
+
+    tb.envelope = None;
+    tb.mode = Realtime;
+    assert_eq!(tb.tick(1000).scene_ns, 1105);
+    assert_eq!(tb.tick(1040).scene_ns, 1145);
 }
@@ -3,7 +3,7 @@
 // TODO: is it possible to have a generic decl for pairsort_*() without
 // resorting to pointers, enums, or dynamic lookup? It must be.
 
-/// Sort a and b in ascending order.
+/// Sort a and b in descending order.
 pub fn pairsort_u8(a: u8, b: u8) -> (u8, u8) {
     if a < b {
         (b, a)
@@ -12,7 +12,7 @@ pub fn pairsort_u8(a: u8, b: u8) -> (u8, u8) {
     }
 }
 
-/// Sort a and b in ascending order.
+/// Sort a and b in descending order.
 pub fn pairsort_i64(a: i64, b: i64) -> (i64, i64) {
     if a < b {
         (b, a)
@@ -21,7 +21,7 @@ pub fn pairsort_i64(a: i64, b: i64) -> (i64, i64) {
     }
 }
 
-/// Sort a and b in ascending order.
+/// Sort a and b in descending order.
 pub fn pairsort_f64(a: f64, b: f64) -> (f64, f64) {
     if a < b {
         (b, a)
@@ -30,7 +30,7 @@ pub fn pairsort_f64(a: f64, b: f64) -> (f64, f64) {
     }
 }
 
-/// Call function f on a and b before returning the results, sorted ascending.
+/// Call function f on a and b before returning the results, sorted descending.
 pub fn sort_apply_f64(f: fn(f64) -> f64, a: f64, b: f64) -> (f64, f64) {
     pairsort_f64(f(a), f(b))
 }
@@ -96,30 +96,31 @@ pub fn limit_unipolar_unit_f64(n: f64) -> f64 {
     }
 }
 
-/// Given a float value normalized to the unipolar unit range [0..1.0], map it
-/// evenly into the byte range [0..255]. Clamp out-of-range input.
-pub fn limit_unipolar_unit_f64_to_u8(n: f64) -> u8 {
-    if n >= 1.0 {
-        255
-    } else if n <= 0.0 {
-        0
+/// Quantize n, clamped to [0.0..1.0], into one of `steps` bins of equal
+/// width, numbered from 0: bin i holds [i/steps..(i+1)/steps), and the last
+/// bin also holds 1.0. Every step thus covers the same share of the interval,
+/// and 0.0 and 1.0 land in the first and last bins. steps must be at least 1.
+pub fn quantize_unit_f64(n: f64, steps: uint) -> uint {
+    let i = (limit_unipolar_unit_f64(n) * steps as f64).floor() as uint;
+    if i >= steps {
+        steps - 1
     } else {
-        // TODO just a sketch - maximize precision
-        (n * 255.999999) as u8
+        i
     }
 }
 
+/// Given a float value normalized to the unipolar unit range [0..1.0], map it
+/// evenly into the byte range [0..255] (see quantize_unit_f64). Clamp
+/// out-of-range input.
+pub fn limit_unipolar_unit_f64_to_u8(n: f64) -> u8 {
+    quantize_unit_f64(n, 256) as u8
+}
+
 /// Given a float value normalized to the bipolar unit range [-1.0..1.0], map it
-/// evenly into the byte range [0..255]. Clamp out-of-range input.
+/// evenly into the byte range [0..255] (see quantize_unit_f64). Clamp
+/// out-of-range input.
 pub fn limit_bipolar_unit_f64_to_u8(n: f64) -> u8 {
-    if n >= 1.0 {
-        255
-    } else if n <= -1.0 {
-        0
-    } else {
-        // TODO just a sketch - maximize precision
-        (255.999999 * (n + 1.0) / 2.0) as u8
-    }
+    quantize_unit_f64((limit_bipolar_unit_f64(n) + 1.0) / 2.0, 256) as u8
 }
 
 /// Given a float value, clamp it to the range [-1.0..1.0].
@@ -161,7 +162,7 @@ pub fn wrap_ring_i64(n: i64, minimum: i64, maximum: i64) -> i64 {
     } else if n < minimum {
         let d = 1 + maximum - minimum;
         let mut nn = n;
-        while nn > maximum {
+        while nn < minimum {
             // FIXME: see above
             nn += d;
         }
@@ -201,3 +202,12 @@ pub fn median_ring_i64(a: i64, b: i64, minimum: i64, maximum: i64) -> i64 {
     }
     // TODO unit test for this to verify rounding
 }
+
+#[test]
+fn test_wrap_ring_i64() {
+    assert_eq!(wrap_ring_i64(5, 0, 9), 5);
+    assert_eq!(wrap_ring_i64(12, 0, 9), 2);
+    assert_eq!(wrap_ring_i64(-1, 0, 9), 9);
+    assert_eq!(wrap_ring_i64(-23, 0, 9), 7);
+    assert_eq!(wrap_ring_i64(0, 1, 4), 4);
+}
@@ -0,0 +1,150 @@
+//! Open Pixel Control output for LED pixel strips.
+//!
+//! An OPC channel is much like a DmxUniverse, except that it may be far
+//! longer than 512 bytes: it is a framebuffer of 8-bit subpixel values,
+//! typically RGB triples. Since the renderers in render.rs just write bytes,
+//! devices patch into an OPC channel the same way they patch into a
+//! universe, via an OpcAddr.
+//!
+//! An OpcClient streams channels to an OPC server (such as fcserver) over
+//! TCP, one set-pixel-colors message per frame.
+
+use std::cell::RefCell;
+use std::cell::RefMut;
+use std::fmt;
+use std::io::IoError;
+use std::io::net::tcp::TcpStream;
+use std::rc::Rc;
+
+/// The default port for OPC servers.
+pub static OPC_PORT: u16 = 7890;
+
+/// The largest payload an OPC message can carry: its length is 16 bits.
+pub static OPC_MAX_LENGTH: uint = 65535;
+
+static CMD_SET_PIXEL_COLORS: u8 = 0;
+
+pub enum OpcError {
+    /// The channel's frame is too long for one message.
+    FrameTooLong(uint),
+    OpcIoError(IoError),
+}
+
+impl fmt::Show for OpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrameTooLong(len) => write!(f, "{} bytes is too long for an OPC message (max {})", len, OPC_MAX_LENGTH),
+            OpcIoError(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// A logical OPC channel: a byte framebuffer of arbitrary length. Channel 0
+/// is a broadcast to every channel on the server.
+pub struct OpcChannel {
+    pub id: u8,
+    pub name: String,
+    pub frame: Vec<u8>,
+}
+
+impl OpcChannel {
+    /// Make a new, blacked out channel of length bytes.
+    pub fn new(id: u8, name: &str, length: uint) -> OpcChannel {
+        OpcChannel {
+            id: id,
+            name: name.to_string(),
+            frame: Vec::from_elem(length, 0u8),
+        }
+    }
+}
+
+/// Situate a Device within a slice of an OpcChannel, analogous to a DmxAddr.
+pub struct OpcAddr {
+    pub channel: Rc<RefCell<OpcChannel>>,
+    pub address: uint, // the byte offset, e.g. 3 * pixel index for RGB pixels
+    pub length: uint,
+}
+
+impl OpcAddr {
+    pub fn try_get_channel_ref<'a>(&'a self) -> Option<RefMut<'a, OpcChannel>> {
+        self.channel.try_borrow_mut()
+    }
+
+    /// Extract the writable slice of the channel that belongs to a particular
+    /// Device. See DmxAddr::slice_universe.
+    pub fn slice_channel<'a>(&self, chan_ref: &'a mut RefMut<'a, OpcChannel>) -> &'a mut [u8] {
+        chan_ref.frame.mut_slice(self.address, self.address + self.length)
+    }
+}
+
+/// Build a set-pixel-colors message: channel, command, big-endian length,
+/// then the data.
+pub fn set_pixel_colors_message(channel: u8, data: &[u8]) -> Result<Vec<u8>, OpcError> {
+    if data.len() > OPC_MAX_LENGTH {
+        return Err(FrameTooLong(data.len()));
+    }
+    let mut m = Vec::with_capacity(4 + data.len());
+    m.push(channel);
+    m.push(CMD_SET_PIXEL_COLORS);
+    m.push((data.len() >> 8) as u8);
+    m.push((data.len() & 0xFF) as u8);
+    m.push_all(data);
+    Ok(m)
+}
+
+pub struct OpcClient {
+    stream: TcpStream,
+}
+
+impl OpcClient {
+    /// Connect to an OPC server, e.g. ("127.0.0.1", OPC_PORT).
+    pub fn connect(host: &str, port: u16) -> Result<OpcClient, OpcError> {
+        match TcpStream::connect(host, port) {
+            Ok(s) => Ok(OpcClient { stream: s }),
+            Err(e) => Err(OpcIoError(e)),
+        }
+    }
+
+    /// Send a channel's whole frame.
+    pub fn send(&mut self, chan: &OpcChannel) -> Result<(), OpcError> {
+        let m = try!(set_pixel_colors_message(chan.id, chan.frame.as_slice()));
+        match self.stream.write(m.as_slice()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(OpcIoError(e)),
+        }
+    }
+}
+
+#[test]
+fn test_opc_client_to_local_server() {
+    use std::io::Acceptor;
+    use std::io::Listener;
+    use std::io::net::tcp::TcpListener;
+    use device::patch_opc;
+    use device::with_endpoint;
+    use device::AbortOnFault;
+    use profile::Continuous;
+    use test_dimmer::{new_tree_root, rgb_pixel};
+
+    let p = rgb_pixel();
+
+    // A strip of 1000 pixels: far more than one DMX universe.
+    let chan = Rc::new(RefCell::new(OpcChannel::new(1, "Strip", 3000)));
+    let mut d = patch_opc(&p, new_tree_root(), 3 * 999, chan.clone()).unwrap();
+    for &i in [0u, 2].iter() {
+        with_endpoint(&d.root, [i], |e, _| e.set_val(Continuous(1.0)));
+    }
+    assert!(d.render(AbortOnFault).is_ok());
+
+    // Port 0 lets the OS pick a free port.
+    let mut listener = TcpListener::bind("127.0.0.1", 0).unwrap();
+    let port = listener.socket_name().unwrap().port;
+    let mut acceptor = listener.listen().unwrap();
+    let mut client = OpcClient::connect("127.0.0.1", port).ok().unwrap();
+    client.send(&*chan.borrow()).ok().unwrap();
+
+    let mut server = acceptor.accept().unwrap();
+    let m = server.read_exact(4 + 3000).unwrap();
+    assert_eq!(m.slice_to(4), &[1u8, 0, 0x0b, 0xb8]);
+    assert_eq!(m.slice_from(4 + 3 * 999), &[255u8, 0, 255]);
+}
@@ -0,0 +1,321 @@
+//! A patch manager: the "higher control layer" that DmxAddr defers to.
+//!
+//! The Patcher owns a set of universes, the devices patched into them, and the
+//! device tree branch that holds those devices' trees. It refuses to patch
+//! invalid profiles, addresses that run off the end of a universe, and
+//! addresses that overlap other devices -- unless the overlap is flagged as
+//! intentional, as it is when several physical fixtures deliberately share an
+//! address. It can also find free addresses automatically.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use device::*;
+use dmx::*;
+use profile::*;
+use validate::Problem;
+use validate::validate_profile;
+
+pub enum PatchError {
+    UnknownUniverse(u32),
+    UnknownDevice(uint),
+    /// The profile did not validate.
+    InvalidProfile(Vec<Problem>),
+    /// (address, length) runs past the end of the universe.
+    OutOfUniverse(uint, uint),
+    /// The ids of the devices already patched at overlapping addresses.
+    Conflict(Vec<uint>),
+    /// No universe has a free block of this many channels.
+    NoFreeBlock(uint),
+    /// The device's first patch is not a DMX patch.
+    NotDmx(uint),
+}
+
+impl fmt::Show for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnknownUniverse(u) => write!(f, "no universe with id {}", u),
+            UnknownDevice(d) => write!(f, "no device with id {}", d),
+            InvalidProfile(ref problems) => write!(f, "invalid profile: {}", problems),
+            OutOfUniverse(a, l) => write!(f, "{} channels at address {} run past the end of the universe", l, a),
+            Conflict(ref ids) => write!(f, "overlaps devices {}", ids),
+            NoFreeBlock(l) => write!(f, "no universe has {} free contiguous channels", l),
+            NotDmx(d) => write!(f, "device {} is not patched to DMX", d),
+        }
+    }
+}
+
+/// One patched device, and whether it may overlap other devices. A flagged
+/// device still occupies its channels: unflagged devices can't be patched on
+/// top of it, and find_free_block steers around it.
+struct PatchEntry<'p> {
+    device: Device<'p>,
+    allow_overlap: bool,
+}
+
+pub struct Patcher<'p> {
+    pub universes: Vec<Rc<RefCell<DmxUniverse>>>,
+
+    /// The branch holding every patched device's tree, in patch order.
+    pub tree_root: Rc<RefCell<DeviceBranch>>,
+
+    entries: Vec<PatchEntry<'p>>,
+    next_id: uint,
+}
+
+impl<'p> Patcher<'p> {
+    pub fn new() -> Patcher<'p> {
+        Patcher {
+            universes: Vec::new(),
+            tree_root: Rc::new(RefCell::new(DeviceBranch {
+                profile_branch: None,
+                children: Vec::new(),
+            })),
+            entries: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Make a new, blacked out universe.
+    pub fn add_universe(&mut self, id: u32, name: &str) -> Rc<RefCell<DmxUniverse>> {
+        let univ = Rc::new(RefCell::new(DmxUniverse {
+            id: id,
+            name: name.to_string(),
+            frame: [0, ..512],
+        }));
+        self.universes.push(univ.clone());
+        univ
+    }
+
+    pub fn universe(&self, id: u32) -> Option<Rc<RefCell<DmxUniverse>>> {
+        self.universes.iter().find(|u| u.borrow().id == id).map(|u| u.clone())
+    }
+
+    pub fn device<'a>(&'a self, id: uint) -> Option<&'a Device<'p>> {
+        self.entries.iter().find(|e| e.device.id == id).map(|e| &e.device)
+    }
+
+    pub fn devices<'a>(&'a self) -> Vec<&'a Device<'p>> {
+        self.entries.iter().map(|e| &e.device).collect()
+    }
+
+    /// List the devices whose DMX patches overlap [address, address + length)
+    /// in the given universe, ignoring the device exclude (if any).
+    pub fn find_conflicts(&self, universe_id: u32, address: uint, length: uint,
+            exclude: Option<uint>) -> Vec<uint> {
+
+        let mut ids = Vec::new();
+        for e in self.entries.iter() {
+            if Some(e.device.id) == exclude {
+                continue;
+            }
+            for p in e.device.patches.iter() {
+                match p.addr {
+                    DmxAddrType(ref a) => {
+                        if a.universe.borrow().id == universe_id
+                                && a.address < address + length
+                                && address < a.address + a.length {
+                            ids.push(e.device.id);
+                            break;
+                        }
+                    },
+                    OpcAddrType(_) => ()
+                }
+            }
+        }
+        ids
+    }
+
+    /// Check that a block is inside its universe and, unless allow_overlap,
+    /// that it is free.
+    fn check_block(&self, universe_id: u32, address: uint, length: uint,
+            allow_overlap: bool, exclude: Option<uint>) -> Result<Rc<RefCell<DmxUniverse>>, PatchError> {
+
+        let univ = match self.universe(universe_id) {
+            Some(u) => u,
+            None => return Err(UnknownUniverse(universe_id)),
+        };
+        if address + length > univ.borrow().frame.len() {
+            return Err(OutOfUniverse(address, length));
+        }
+        if !allow_overlap {
+            let conflicts = self.find_conflicts(universe_id, address, length, exclude);
+            if !conflicts.is_empty() {
+                return Err(Conflict(conflicts));
+            }
+        }
+        Ok(univ)
+    }
+
+    /// Validate a profile and patch an instance of it at the given address.
+    /// Return the new device's id.
+    pub fn patch(&mut self, profile: &'p Profile, universe_id: u32, address: uint,
+            allow_overlap: bool) -> Result<uint, PatchError> {
+
+        let report = validate_profile(profile);
+        if !report.is_valid() {
+            return Err(InvalidProfile(report.problems));
+        }
+        let length = match profile.chan_alloc {
+            DmxChannelCount(len) => len,
+        };
+        let univ = try!(self.check_block(universe_id, address, length, allow_overlap, None));
+
+        // unwrap() is safe: device::patch handles every ChannelAlloc we have.
+        let mut d = patch(profile, self.tree_root.clone(), address, univ).unwrap();
+        d.id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(PatchEntry { device: d, allow_overlap: allow_overlap });
+        Ok(self.next_id - 1)
+    }
+
+    /// Find the first free block of length channels, searching the universes
+    /// in order and each universe from channel 0.
+    pub fn find_free_block(&self, length: uint) -> Option<(u32, uint)> {
+        for univ in self.universes.iter() {
+            let (id, frame_len) = {
+                let u = univ.borrow();
+                (u.id, u.frame.len())
+            };
+            let mut address = 0u;
+            while address + length <= frame_len {
+                let conflicts = self.find_conflicts(id, address, length, None);
+                if conflicts.is_empty() {
+                    return Some((id, address));
+                }
+                // Skip past the furthest-reaching device in the way.
+                address = conflicts.iter().map(|&c| self.end_of(c, id)).max().unwrap();
+            }
+        }
+        None
+    }
+
+    /// The first channel after device id's patch(es) in the given universe.
+    fn end_of(&self, id: uint, universe_id: u32) -> uint {
+        let mut end = 0;
+        for p in self.device(id).unwrap().patches.iter() {
+            match p.addr {
+                DmxAddrType(ref a) if a.universe.borrow().id == universe_id => {
+                    if a.address + a.length > end {
+                        end = a.address + a.length;
+                    }
+                },
+                _ => ()
+            }
+        }
+        end
+    }
+
+    /// Patch each profile at the next free contiguous block, across all the
+    /// universes. Either all of them are patched, or none are.
+    pub fn auto_patch(&mut self, profiles: &[&'p Profile]) -> Result<Vec<uint>, PatchError> {
+        let mut ids = Vec::new();
+        for &profile in profiles.iter() {
+            let length = match profile.chan_alloc {
+                DmxChannelCount(len) => len,
+            };
+            let result = match self.find_free_block(length) {
+                Some((univ, address)) => self.patch(profile, univ, address, false),
+                None => Err(NoFreeBlock(length)),
+            };
+            match result {
+                Ok(id) => ids.push(id),
+                Err(e) => {
+                    for &id in ids.iter() {
+                        let _ = self.unpatch(id);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Remove a device and its tree.
+    pub fn unpatch(&mut self, id: uint) -> Result<(), PatchError> {
+        let i = match self.entries.iter().position(|e| e.device.id == id) {
+            Some(i) => i,
+            None => return Err(UnknownDevice(id)),
+        };
+        let e = self.entries.remove(i).unwrap();
+        // Find the device's tree by identity, not position, in case anyone
+        // else has added to tree_root.
+        let root = &*e.device.root as *const _;
+        let mut tree_root = self.tree_root.borrow_mut();
+        match tree_root.children.iter().position(|c| &**c as *const _ == root) {
+            Some(j) => { tree_root.children.remove(j); },
+            None => ()
+        }
+        Ok(())
+    }
+
+    /// Move a device's (first) DMX patch to a new address, keeping its device
+    /// tree and therefore its state.
+    pub fn readdress(&mut self, id: uint, universe_id: u32, address: uint) -> Result<(), PatchError> {
+        let i = match self.entries.iter().position(|e| e.device.id == id) {
+            Some(i) => i,
+            None => return Err(UnknownDevice(id)),
+        };
+        let (length, allow_overlap) = {
+            let e = self.entries.get(i);
+            match e.device.patches.get(0).addr {
+                DmxAddrType(ref a) => (a.length, e.allow_overlap),
+                OpcAddrType(_) => return Err(NotDmx(id)),
+            }
+        };
+        let univ = try!(self.check_block(universe_id, address, length, allow_overlap, Some(id)));
+        match self.entries.get_mut(i).device.patches.get_mut(0).addr {
+            DmxAddrType(ref mut a) => {
+                a.universe = univ;
+                a.address = address;
+            },
+            OpcAddrType(_) => return Err(NotDmx(id)),
+        }
+        Ok(())
+    }
+
+    /// Render every patched device. See Device::render.
+    pub fn render(&mut self, policy: RenderPolicy) -> Result<Vec<RenderError>, RenderError> {
+        let mut faults = Vec::new();
+        for e in self.entries.mut_iter() {
+            faults.push_all_move(try!(e.device.render(policy)));
+        }
+        Ok(faults)
+    }
+}
+
+#[test]
+fn test_patcher() {
+    use test_dimmer::dimmer_pair;
+
+    let p = dimmer_pair();
+
+    let mut patcher = Patcher::new();
+    patcher.add_universe(1, "U1");
+    patcher.add_universe(2, "U2");
+
+    let a = patcher.patch(&p, 1, 0, false).ok().unwrap();
+    assert!(patcher.patch(&p, 1, 1, false).is_err());
+    assert!(patcher.patch(&p, 1, 511, false).is_err());
+    let b = patcher.patch(&p, 1, 1, true).ok().unwrap();
+
+    // 0..2 and 1..3 are taken, so the next free block starts at 3.
+    assert_eq!(patcher.find_free_block(2), Some((1, 3)));
+    assert_eq!(patcher.find_free_block(510), Some((2, 0)));
+
+    assert!(patcher.readdress(a, 2, 10).is_ok());
+    assert!(patcher.unpatch(b).is_ok());
+    assert_eq!(patcher.find_free_block(2), Some((1, 0)));
+    assert_eq!(patcher.tree_root.borrow().children.len(), 1);
+
+    // A tree added behind the Patcher's back doesn't confuse unpatch.
+    let c = patcher.patch(&p, 1, 0, false).ok().unwrap();
+    patcher.tree_root.borrow_mut().children.insert(0, device_subtree_from_profile_subtree(&p.root));
+    let stray = patcher.tree_root.borrow().children.get(0).clone();
+    assert!(patcher.unpatch(c).is_ok());
+    let tree_root = patcher.tree_root.borrow();
+    assert_eq!(tree_root.children.len(), 2);
+    assert!(&**tree_root.children.get(0) as *const _ == &*stray as *const _);
+    assert!(&**tree_root.children.get(1) as *const _ == &*patcher.device(a).unwrap().root as *const _);
+}
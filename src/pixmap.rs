@@ -0,0 +1,332 @@
+//! Pixel mapping: play a 2D raster, such as a frame of video, across a rig.
+//!
+//! A Projection lays the raster out on a plane in the world, like a screen.
+//! Each fixture looks up the color of the raster at its position projected
+//! onto that plane, so content lands on the rig the way it hangs, however
+//! irregularly. Rasters are plain RGB framebuffers; whatever decodes the
+//! video writes frames into one, and binary or ASCII PPM files may be loaded
+//! directly.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io::File;
+use std::io::IoError;
+use std::rc::Rc;
+
+use device::*;
+use effect::Color;
+use effect::ColorspaceRgb;
+use generator::fit_to_topo;
+use mixer::AnimatorPlugin;
+use mixer::Timepoint;
+use profile::*;
+use world::Position;
+
+pub enum PixmapError {
+    /// The file is not a PPM we can read.
+    BadPpm(String),
+    PixmapIoError(IoError),
+}
+
+impl fmt::Show for PixmapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BadPpm(ref msg) => write!(f, "bad PPM: {}", msg),
+            PixmapIoError(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// An RGB framebuffer: 8 bits per subpixel, row by row from the top left.
+pub struct Raster {
+    pub width: uint,
+    pub height: uint,
+    pub data: Vec<u8>,
+}
+
+impl Raster {
+    /// Make a new, black raster.
+    pub fn new(width: uint, height: uint) -> Raster {
+        Raster { width: width, height: height, data: Vec::from_elem(3 * width * height, 0u8) }
+    }
+
+    /// Wrap existing RGB data. Return None if data is the wrong length.
+    pub fn from_rgb(width: uint, height: uint, data: Vec<u8>) -> Option<Raster> {
+        if data.len() != 3 * width * height {
+            return None;
+        }
+        Some(Raster { width: width, height: height, data: data })
+    }
+
+    pub fn set(&mut self, x: uint, y: uint, rgb: (u8, u8, u8)) {
+        let i = 3 * (y * self.width + x);
+        let (r, g, b) = rgb;
+        *self.data.get_mut(i) = r;
+        *self.data.get_mut(i + 1) = g;
+        *self.data.get_mut(i + 2) = b;
+    }
+
+    /// The color of one pixel, each component in [0.0..1.0].
+    pub fn get(&self, x: uint, y: uint) -> (f64, f64, f64) {
+        let i = 3 * (y * self.width + x);
+        let d = self.data.slice(i, i + 3);
+        (d[0] as f64 / 255.0, d[1] as f64 / 255.0, d[2] as f64 / 255.0)
+    }
+
+    /// The color at (s, t), where (0, 0) is the top left corner of the
+    /// raster and (1, 1) the bottom right. Return None outside the raster.
+    pub fn sample(&self, s: f64, t: f64, filter: SampleFilter) -> Option<(f64, f64, f64)> {
+        if self.width == 0 || self.height == 0 || s < 0.0 || s > 1.0 || t < 0.0 || t > 1.0 {
+            return None;
+        }
+        let (w, h) = (self.width, self.height);
+        match filter {
+            NearestFilter => {
+                let x = ((s * w as f64).floor() as uint).min(w - 1);
+                let y = ((t * h as f64).floor() as uint).min(h - 1);
+                Some(self.get(x, y))
+            },
+            BilinearFilter => {
+                // Pixel centers are at half-pixel offsets; hold the edge
+                // pixels out to the edges.
+                let fx = (s * w as f64 - 0.5).max(0.0).min((w - 1) as f64);
+                let fy = (t * h as f64 - 0.5).max(0.0).min((h - 1) as f64);
+                let (x0, y0) = (fx.floor() as uint, fy.floor() as uint);
+                let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+                let (u, v) = (fx - x0 as f64, fy - y0 as f64);
+                let lerp = |a: (f64, f64, f64), b: (f64, f64, f64), k: f64| {
+                    let ((ar, ag, ab), (br, bg, bb)) = (a, b);
+                    (ar + k * (br - ar), ag + k * (bg - ag), ab + k * (bb - ab))
+                };
+                let top = lerp(self.get(x0, y0), self.get(x1, y0), u);
+                let bottom = lerp(self.get(x0, y1), self.get(x1, y1), u);
+                Some(lerp(top, bottom, v))
+            },
+        }
+    }
+}
+
+/// Read a binary (P6) or ASCII (P3) PPM file.
+pub fn load_ppm(path: &Path) -> Result<Raster, PixmapError> {
+    match File::open(path).read_to_end() {
+        Ok(bytes) => parse_ppm(bytes.as_slice()),
+        Err(e) => Err(PixmapIoError(e)),
+    }
+}
+
+/// Parse a binary (P6) or ASCII (P3) PPM image. Images with a maximum value
+/// other than 255 are rescaled; 16-bit images are not supported.
+pub fn parse_ppm(bytes: &[u8]) -> Result<Raster, PixmapError> {
+    let mut pos = 0u;
+
+    let binary = match next_token(bytes, &mut pos) {
+        Some(ref m) if m.as_slice() == "P6" => true,
+        Some(ref m) if m.as_slice() == "P3" => false,
+        _ => return Err(BadPpm("not a P3 or P6 file".to_string())),
+    };
+    let width = try!(next_uint(bytes, &mut pos, "width"));
+    let height = try!(next_uint(bytes, &mut pos, "height"));
+    let maxval = try!(next_uint(bytes, &mut pos, "maximum value"));
+    if maxval == 0 || maxval > 255 {
+        return Err(BadPpm(format!("unsupported maximum value {}", maxval)));
+    }
+
+    let len = 3 * width * height;
+    let mut data = Vec::with_capacity(len);
+    if binary {
+        // Exactly one whitespace byte separates the header from the data.
+        pos += 1;
+        if pos + len > bytes.len() {
+            return Err(BadPpm("truncated pixel data".to_string()));
+        }
+        data.push_all(bytes.slice(pos, pos + len));
+    } else {
+        for _ in range(0, len) {
+            let v = try!(next_uint(bytes, &mut pos, "pixel data"));
+            if v > maxval {
+                return Err(BadPpm(format!("sample {} exceeds the maximum value", v)));
+            }
+            data.push(v as u8);
+        }
+    }
+    if maxval != 255 {
+        for v in data.mut_iter() {
+            *v = ((*v as uint * 255 + maxval / 2) / maxval) as u8;
+        }
+    }
+    Ok(Raster { width: width, height: height, data: data })
+}
+
+/// Read the next whitespace-delimited token of a PPM header, skipping
+/// comments.
+fn next_token(bytes: &[u8], pos: &mut uint) -> Option<String> {
+    loop {
+        while *pos < bytes.len() && (bytes[*pos] as char).is_whitespace() {
+            *pos += 1;
+        }
+        if *pos < bytes.len() && bytes[*pos] == '#' as u8 {
+            while *pos < bytes.len() && bytes[*pos] != '\n' as u8 {
+                *pos += 1;
+            }
+        } else {
+            break;
+        }
+    }
+    let start = *pos;
+    while *pos < bytes.len() && !(bytes[*pos] as char).is_whitespace() {
+        *pos += 1;
+    }
+    if start == *pos {
+        return None;
+    }
+    Some(bytes.slice(start, *pos).iter().map(|&b| b as char).collect())
+}
+
+fn next_uint(bytes: &[u8], pos: &mut uint, what: &str) -> Result<uint, PixmapError> {
+    match next_token(bytes, pos).and_then(|t| from_str::<uint>(t.as_slice())) {
+        Some(n) => Ok(n),
+        None => Err(BadPpm(format!("expected the {}", what))),
+    }
+}
+
+#[deriving(Clone,PartialEq,Show)]
+pub enum SampleFilter {
+    /// The pixel the point falls in: crisp, but steps as content moves.
+    NearestFilter,
+    /// Interpolate between the four nearest pixel centers.
+    BilinearFilter,
+}
+
+/// Lay a raster out on a plane in the world. Points off the plane are
+/// projected straight onto it.
+pub struct Projection {
+    /// Where the top left corner of the raster is.
+    pub origin: Position,
+    /// From the top left corner to the top right corner.
+    pub across: Position,
+    /// From the top left corner to the bottom left corner. Must be
+    /// perpendicular to across.
+    pub down: Position,
+}
+
+impl Projection {
+    /// Where p lands on the raster, in the raster coordinates used by
+    /// Raster::sample.
+    pub fn project(&self, p: &Position) -> (f64, f64) {
+        let d = Position::new(p.x - self.origin.x, p.y - self.origin.y, p.z - self.origin.z);
+        (d.dot(&self.across) / self.across.dot(&self.across),
+         d.dot(&self.down) / self.down.dot(&self.down))
+    }
+}
+
+/// What a pixel mapped endpoint takes from the color of its pixel.
+#[deriving(Clone,PartialEq,Show)]
+pub enum PixelChannel {
+    RedChannel,
+    GreenChannel,
+    BlueChannel,
+    /// Brightness, for single color fixtures.
+    LumaChannel,
+}
+
+/// A fixture to pixel map: where it is, and which of its endpoints take which
+/// channels of the color at that point.
+pub struct PixelTarget {
+    pub position: Position,
+    pub channels: Vec<(PixelChannel, DeviceTreePath)>,
+}
+
+/// The paths to the RGB color endpoints below root, in tree order. For a
+/// simple RGB fixture, these are its red, green and blue.
+pub fn rgb_paths(root: &Rc<RefCell<DeviceTree>>) -> Vec<DeviceTreePath> {
+    fn walk(node: &Rc<RefCell<DeviceTree>>, path: &mut DeviceTreePath, found: &mut Vec<DeviceTreePath>) {
+        let children = match *node.borrow() {
+            DeviceTreeEndpoint(ref e) => {
+                match *e.attribute.borrow() {
+                    ProfileGraphAttribute(ref a) => match a.effect {
+                        (Color, ColorspaceRgb, _) => found.push(path.clone()),
+                        _ => ()
+                    },
+                    _ => ()
+                }
+                return;
+            },
+            DeviceTreeBranch(ref b) => b.children.clone(),
+            DeviceTreeSwitch(ref s) => s.children.clone(),
+        };
+        for (i, child) in children.iter().enumerate() {
+            path.push(i);
+            walk(child, path, found);
+            path.pop();
+        }
+    }
+    let mut found = Vec::new();
+    walk(root, &mut Vec::new(), &mut found);
+    found
+}
+
+/// Map a raster onto a group of fixtures. Fixtures that fall outside the
+/// raster are left alone.
+pub struct PixelMapper {
+    /// Shared, so that a video source can write each new frame into it.
+    pub raster: Rc<RefCell<Raster>>,
+    pub projection: Projection,
+    pub filter: SampleFilter,
+    pub targets: Vec<PixelTarget>,
+}
+
+impl AnimatorPlugin for PixelMapper {
+    fn animate(&mut self, _time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+            _frame: Option<&Rc<RefCell<DeviceTree>>>) {
+        let raster = self.raster.borrow();
+        for target in self.targets.iter() {
+            let (s, t) = self.projection.project(&target.position);
+            let (r, g, b) = match raster.sample(s, t, self.filter) {
+                Some(rgb) => rgb,
+                None => continue,
+            };
+            for &(channel, ref path) in target.channels.iter() {
+                let level = match channel {
+                    RedChannel => r,
+                    GreenChannel => g,
+                    BlueChannel => b,
+                    // Rec. 709 luma
+                    LumaChannel => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+                };
+                with_endpoint(scene, path.as_slice(), |e, a| e.set_val(fit_to_topo(a, level)));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_pixel_mapping() {
+    use test_dimmer::{at, close, continuous_at, rgb_pixel};
+
+    // Red then blue, hanging on the x/z plane: 2m wide, 1m tall, top at 3m.
+    let raster = parse_ppm(b"P3\n# two pixels\n2 1\n15\n15 0 0  0 0 15\n").ok().unwrap();
+    assert_eq!(raster.data, vec!(255u8, 0, 0, 0, 0, 255));
+    let projection = Projection { origin: Position::new(0.0, 0.0, 3.0),
+        across: Position::new(2.0, 0.0, 0.0), down: Position::new(0.0, 0.0, -1.0) };
+    let (s, t) = projection.project(&Position::new(1.0, 5.0, 2.5));
+    assert!(close(s, 0.5) && close(t, 0.5));
+    assert_eq!(raster.sample(0.4, 0.5, NearestFilter), Some((1.0, 0.0, 0.0)));
+    assert_eq!(raster.sample(0.5, 0.5, BilinearFilter), Some((0.5, 0.0, 0.5)));
+    assert_eq!(raster.sample(1.5, 0.5, BilinearFilter), None);
+
+    let p = rgb_pixel();
+    let scene = device_subtree_from_profile_subtree(&p.root);
+    let paths = rgb_paths(&scene);
+    assert_eq!(paths, vec!(vec!(0u), vec!(1u), vec!(2u)));
+
+    let channels = vec!(RedChannel, GreenChannel, BlueChannel);
+    let mut mapper = PixelMapper {
+        raster: Rc::new(RefCell::new(raster)),
+        projection: projection,
+        filter: NearestFilter,
+        targets: vec!(PixelTarget { position: Position::new(1.5, 0.0, 2.5),
+            channels: channels.move_iter().zip(paths.move_iter()).collect() }),
+    };
+    mapper.animate(&at(0), &scene, None);
+    assert_eq!(continuous_at(&scene, [2]), 1.0);
+}
@@ -1,12 +1,18 @@
 //! Models for describing device types (profiles) as digraphs.
 
 use std::cell::RefCell;
+use std::fmt;
 use std::rc::Rc;
 
+use aim::YokeGeometry;
+use color::WheelSlot;
 use dmx::DmxMap;
 use effect::EffectType;
 use effect::EffectSubtype;
 use effect::EffectSubsubtype;
+use range::Range;
+use render::DmxIntIndexedWithRangeRenderer;
+use topo::BlendMode;
 use topo::Topo;
 
 /// Hypothesis: devices' descriptions are trees of ProfileElements, and this will
@@ -21,12 +27,14 @@ pub struct Profile {
     pub date: String,       // maybe we want to make this some kind of timestamp type
     pub version: int,       // 1, 2, 3...
     pub chan_alloc: ChannelAlloc, // what kinds of addresses do we need to allocate to patch one?
+    pub yoke: Option<Rc<YokeGeometry>>, // moving heads only: how the pan and tilt move
 
     pub root: Rc<RefCell<ProfileGraph>>,
 }
 
 /// We will gradually expand the ways we can allocate channels, potentially
 /// across multiple universes and even protocols.
+#[deriving(Clone)]
 pub enum ChannelAlloc {
     DmxChannelCount(uint),
 }
@@ -53,6 +61,27 @@ pub enum ProfileGraph {
     ProfileGraphSwitch(ProfileSwitch), // an exclusive modal switch branch node
 }
 
+impl ProfileGraph {
+    /// The nickname of this node, whatever kind of node it is. Nicknames name
+    /// the steps of a ProfilePath.
+    pub fn nickname<'a>(&'a self) -> &'a str {
+        match *self {
+            ProfileGraphAttribute(ref a) => a.nickname.as_slice(),
+            ProfileGraphBranch(ref b) => b.nickname.as_slice(),
+            ProfileGraphSwitch(ref s) => s.nickname.as_slice(),
+        }
+    }
+
+    /// The children of a branch or switch node. Attributes have none.
+    pub fn children<'a>(&'a self) -> &'a [Rc<RefCell<ProfileGraph>>] {
+        match *self {
+            ProfileGraphAttribute(_) => &[],
+            ProfileGraphBranch(ref b) => b.children.as_slice(),
+            ProfileGraphSwitch(ref s) => s.children.as_slice(),
+        }
+    }
+}
+
 // Named subtypes for the primitive storage representing the numeric value for
 // a Device Attribute's instance.
 #[deriving(Clone)]
@@ -69,6 +98,36 @@ pub struct Attribute {
     pub topo: &'static Topo,
     pub default: Option<AttributeValue>, // required if rendering is implemented
     pub dmx: Option<DmxMap>, // required if DMX rendering is implemented
+    pub wheel: Option<Vec<WheelSlot>>, // color wheels only: the color at each index
+}
+
+impl Attribute {
+    /// The range of valid values of an indexed attribute: 0 through the index
+    /// of its last DMX range. None if that isn't known.
+    pub fn discrete_range(&self) -> Option<Range<i64>> {
+        match self.dmx {
+            Some(ref dmx) => match dmx.renderer {
+                DmxIntIndexedWithRangeRenderer(_, ref ranges) if !ranges.is_empty() =>
+                    Some(Range { min: 0, max: ranges.len() as i64 - 1 }),
+                _ => None
+            },
+            None => None
+        }
+    }
+
+    /// Blend a (on top) with b through this attribute's topology. See
+    /// Topo::blend.
+    pub fn blend(&self, mode: BlendMode, a: AttributeValue, b: AttributeValue)
+            -> Option<AttributeValue> {
+        self.topo.blend(mode, a, b, self.discrete_range().as_ref())
+    }
+
+    /// Mix from b toward a by alpha through this attribute's topology. See
+    /// Topo::mix.
+    pub fn mix(&self, a: AttributeValue, b: AttributeValue, alpha: f64)
+            -> Option<AttributeValue> {
+        self.topo.mix(a, b, alpha, self.discrete_range().as_ref())
+    }
 }
 
 /// An ordinary, inclusive branch node, used to group Profile subgraphs.
@@ -103,3 +162,189 @@ pub struct ProfileSwitch {
     pub children: Vec<Rc<RefCell<ProfileGraph>>>,
     pub default_selection: uint, // TODO: might need to be Option<uint> for construction purposes, but hopefully not
 }
+
+/// Locate a node within a profile by the nicknames of the nodes on the way
+/// down from the root, separated by '/', e.g. "Color/Wheel". The root itself is
+/// not named in the path; the empty path "" denotes the root.
+pub type ProfilePath = String;
+
+/// One change to apply while deriving a variant from a base Profile.
+pub enum ProfileEdit {
+    /// Replace the node at the path (and its whole subgraph) with another.
+    ReplaceNode(ProfilePath, Rc<RefCell<ProfileGraph>>),
+    /// Append a child to the branch or switch at the path.
+    AddChild(ProfilePath, Rc<RefCell<ProfileGraph>>),
+    /// Remove the node at the path from its parent. A switch's default
+    /// selection can't be removed.
+    RemoveNode(ProfilePath),
+}
+
+/// Describe an edit that could not be applied, e.g. because its path did not
+/// lead anywhere.
+pub struct DeriveError {
+    pub path: ProfilePath,
+    pub msg: String,
+}
+
+impl fmt::Show for DeriveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.msg)
+    }
+}
+
+/// Derive a variant from a base profile by applying edits in order.
+///
+/// Only the edited nodes and their ancestors are rebuilt. Every other node of
+/// the variant is the very same Rc as in the base, so a variant costs only its
+/// differences (see ProfileGraph). The variant inherits the base's metadata;
+/// rename it with struct update syntax, e.g.
+/// Profile { name: "Technobeam-i".to_string(), ..variant }.
+pub fn derive_profile(base: &Profile, edits: &[ProfileEdit]) -> Result<Profile, DeriveError> {
+    let mut root = base.root.clone();
+    for edit in edits.iter() {
+        let path = match *edit {
+            ReplaceNode(ref p, _) | AddChild(ref p, _) | RemoveNode(ref p) => p,
+        };
+        let steps: Vec<&str> = path.as_slice().split('/').filter(|s| !s.is_empty()).collect();
+        root = match edit_subgraph(&root, steps.as_slice(), edit) {
+            Ok(r) => r,
+            Err(msg) => return Err(DeriveError { path: path.clone(), msg: msg }),
+        };
+    }
+    Ok(Profile {
+        name: base.name.clone(),
+        nickname: base.nickname.clone(),
+        manufacturer: base.manufacturer.clone(),
+        author: base.author.clone(),
+        date: base.date.clone(),
+        version: base.version,
+        chan_alloc: base.chan_alloc.clone(),
+        yoke: base.yoke.clone(),
+        root: root,
+    })
+}
+
+/// Apply one edit below node, returning the rebuilt node. steps is what
+/// remains of the edit's path.
+fn edit_subgraph(node: &Rc<RefCell<ProfileGraph>>, steps: &[&str],
+        edit: &ProfileEdit) -> Result<Rc<RefCell<ProfileGraph>>, String> {
+
+    // Removal must be done by the parent of the doomed node.
+    match *edit {
+        RemoveNode(_) if steps.len() == 1 => {
+            let i = try!(child_index(node, steps[0]));
+            return rebuild_with_children(node, |children| {
+                children.remove(i);
+            }, Some(i));
+        },
+        RemoveNode(_) if steps.len() == 0 => {
+            return Err("the root can't be removed".to_string());
+        },
+        _ => ()
+    }
+
+    if steps.len() == 0 {
+        return match *edit {
+            ReplaceNode(_, ref n) => Ok(n.clone()),
+            AddChild(_, ref n) => {
+                match *node.borrow() {
+                    ProfileGraphAttribute(_) => return Err("can't add a child to an attribute".to_string()),
+                    _ => ()
+                }
+                rebuild_with_children(node, |children| children.push(n.clone()), None)
+            },
+            RemoveNode(_) => unreachable!(),
+        };
+    }
+
+    let i = try!(child_index(node, steps[0]));
+    let child = node.borrow().children()[i].clone();
+    let new_child = try!(edit_subgraph(&child, steps.slice_from(1), edit));
+    rebuild_with_children(node, |children| {
+        *children.get_mut(i) = new_child.clone();
+    }, None)
+}
+
+fn child_index(node: &Rc<RefCell<ProfileGraph>>, nickname: &str) -> Result<uint, String> {
+    let n = node.borrow();
+    match n.children().iter().position(|c| c.borrow().nickname() == nickname) {
+        Some(i) => Ok(i),
+        None => Err(format!("\"{}\" has no child named \"{}\"", n.nickname(), nickname)),
+    }
+}
+
+/// Make a new branch or switch like node, but with its (shallowly copied)
+/// children modified by f. Pass the index of a removed child, if any, so that
+/// a switch's default selection keeps pointing at the same child.
+fn rebuild_with_children(node: &Rc<RefCell<ProfileGraph>>,
+        f: |&mut Vec<Rc<RefCell<ProfileGraph>>>|,
+        removed: Option<uint>) -> Result<Rc<RefCell<ProfileGraph>>, String> {
+
+    let rebuilt = match *node.borrow() {
+        ProfileGraphAttribute(ref a) => return Err(format!("\"{}\" is an attribute and has no children", a.nickname)),
+        ProfileGraphBranch(ref b) => {
+            let mut children = b.children.clone();
+            f(&mut children);
+            ProfileGraphBranch(ProfileBranch {
+                name: b.name.clone(),
+                nickname: b.nickname.clone(),
+                children: children,
+            })
+        },
+        ProfileGraphSwitch(ref s) => {
+            let default_selection = match removed {
+                Some(i) if i == s.default_selection =>
+                    return Err(format!("can't remove the default selection of \"{}\"", s.nickname)),
+                Some(i) if i < s.default_selection => s.default_selection - 1,
+                _ => s.default_selection,
+            };
+            let mut children = s.children.clone();
+            f(&mut children);
+            ProfileGraphSwitch(ProfileSwitch {
+                name: s.name.clone(),
+                nickname: s.nickname.clone(),
+                children: children,
+                default_selection: default_selection,
+            })
+        },
+    };
+    Ok(Rc::new(RefCell::new(rebuilt)))
+}
+
+#[test]
+fn test_derive_profile_shares_untouched_nodes() {
+    use loader::load_profile;
+
+    let base = load_profile(r#"{
+        "name": "Fixture", "nickname": "Fix", "channels": 2,
+        "root": {"branch": "Fixture", "nickname": "Fix", "children": [
+            {"attribute": "Dimmer", "nickname": "Dim",
+             "effect": ["Dimmer", "ColorspaceI", "Value"],
+             "topo": "continuous_euclidian_unipolar", "default": 0.0},
+            {"branch": "Color", "nickname": "Color", "children": [
+                {"attribute": "Wheel", "nickname": "Wheel",
+                 "effect": ["Color", "Colorspace1x", "Value"],
+                 "topo": "discrete_ring", "default": 0}
+            ]}
+        ]}
+    }"#).ok().unwrap();
+
+    let wheel = base.root.borrow().children()[1].borrow().children()[0].clone();
+    let new_wheel = wheel.clone(); // stand-in for a customized wheel
+    let variant = derive_profile(&base, [
+        ReplaceNode("Color/Wheel".to_string(), new_wheel),
+        AddChild("Color".to_string(), wheel.clone()),
+    ]).ok().unwrap();
+
+    let root = variant.root.borrow();
+    let color = root.children()[1].borrow();
+    assert_eq!(color.children().len(), 2);
+
+    // The dimmer is untouched, so the variant holds the base's own node; the
+    // color branch was rebuilt.
+    let base_root = base.root.borrow();
+    assert!(&*root.children()[0] as *const _ == &*base_root.children()[0] as *const _);
+    assert!(&*root.children()[1] as *const _ != &*base_root.children()[1] as *const _);
+
+    assert!(derive_profile(&base, [RemoveNode("Nope".to_string())]).is_err());
+}
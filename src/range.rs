@@ -14,9 +14,47 @@ pub struct Range <T> {
     pub max: T,
 }
 
+impl<T: PartialOrd> Range<T> {
+    /// True if v lies within this range, inclusively. Reverse ranges (where
+    /// min > max) are accepted.
+    pub fn contains(&self, v: &T) -> bool {
+        (self.min <= *v && *v <= self.max) || (self.max <= *v && *v <= self.min)
+    }
+}
+
 /// A range from [0...256], for encoding a single DMX channel.
 pub type DmxRange = Range<u8>;
 
+impl Range<u8> {
+    /// The number of channel values in this range, inclusive.
+    pub fn steps(&self) -> uint {
+        if self.min <= self.max {
+            (self.max - self.min) as uint + 1
+        } else {
+            (self.min - self.max) as uint + 1
+        }
+    }
+
+    /// The ith channel value, counting from min towards max (downwards, for a
+    /// reverse range). i must be less than steps().
+    pub fn step(&self, i: uint) -> u8 {
+        if self.min <= self.max {
+            self.min + i as u8
+        } else {
+            self.min - i as u8
+        }
+    }
+
+    /// The inverse of step: how far v lies from min. v must lie in range.
+    pub fn step_of(&self, v: u8) -> uint {
+        if self.min <= v {
+            (v - self.min) as uint
+        } else {
+            (self.min - v) as uint
+        }
+    }
+}
+
 
 /// A bipolar range matrix, as a 5x2 sequence in this form:
 /// [
@@ -37,13 +75,16 @@ pub type DmxRange = Range<u8>;
 ///   Negative intermediate values are mapped linearly from r[1][0] to r[1][1].
 ///   x = 0.0 maps to the channel value specified at attribute.range[2][0]
 ///   Positive intermediate values are mapped linearly from r[3][0] to r[3][1].
-///   x = 1.0 maps to the channel value specified at attribute.range[3][1]
+///   x = 1.0 maps to the channel value specified at attribute.range[4][0]
+///
+/// Intermediate values are spread evenly: each channel value in neg or pos
+/// stands for an equal share of its row (see render.rs).
 ///
 /// Reverse ranges (where low values map to high channel values) are accepted.
 pub struct BipolarChannelValueRangeMatrix<T> {
     // N.B. neg.min may be greater than neg.max, or pos.min may be greater than
     // pos.max, inverting the interpolation for the respective subrange.
-    // See renderDMXFloatBipolarWithRange for details.
+    // See render_dmx_float_bipolar_with_range for details.
     pub min: Range<T>, // Values equivalent to -1.0
     pub neg: Range<T>, // Values in the range (-1.0..0.0), exclusive
     pub mid: Range<T>, // Values equivalent to 0.0
@@ -69,12 +110,13 @@ pub type BipolarDmxRangeMatrix = BipolarChannelValueRangeMatrix<u8>;
 /// For example,
 ///   x=0 maps to the channel value specified at attribute.range[0][0]
 ///   x=1.0 maps to the channel value specified at attribute.range[2][0]
-///   Intermediate values are mapped linearly from r[1][0] to r[1][1].
+///   Intermediate values are mapped linearly from r[1][0] to r[1][1], each
+///   channel value standing for an equal share of the row (see render.rs).
 ///
 /// Reverse ranges (where low values map to high channel values) are accepted.
 pub struct UnipolarRangeMatrix<T> {
     // N.B. mid.min may be greater than mid.max, inverting the interpolation.
-    // See renderDMXFloatWithRange for details.
+    // See render_dmx_float_unipolar_with_range for details.
     pub min: Range<T>, // Values equivalent to 0.0
     pub mid: Range<T>, // Values in the range (0.0.. 1.0), exclusive
     pub max: Range<T>, // Values equivalent to 1.0
@@ -98,7 +140,7 @@ pub type UnipolarDmxRangeMatrix = UnipolarRangeMatrix<u8>;
 /// channel level (reverse for negative, stop for 0, fwd for positive).
 ///
 /// The magnitude of value is interpreted as speed. Currently speed renders
-// linearly from 0 (still) to 255 (fastest). See below.
+/// linearly from 1 (slowest) to 255 (fastest), with 0 only when stopped.
 pub struct SpinRangeMatrix<T> {
     pub rev:  Range<T>, // Reverse values, slow through fast
     pub stop: Range<T>, // Values equivalent to stationary
@@ -1,13 +1,25 @@
 //! Dmx rendering primitives.
+//!
+//! Quantization follows one rule throughout. A continuous interval rendered
+//! onto k channel values is split into k bins of equal width, one per channel
+//! value, in order (see numeric::quantize_unit_f64). Where the interval is
+//! closed, as for the plain float, double and fine renderers, 0.0 and 1.0
+//! fall in the end bins and decode exactly: channel value i decodes to
+//! i/(k-1). Where it is open, as for the interpolated rows of a range matrix
+//! (whose ends belong to the neighbouring exact rows), channel value i decodes
+//! to the center of its bin, (i+0.5)/k. Either way, rendering a decoded value
+//! yields the same channel value again. Reverse ranges count down from min.
 
+use dmx::DmxAddressOffset;
 use numeric::limit_bipolar_unit_f64;
 use numeric::limit_bipolar_unit_f64_to_u8;
 use numeric::limit_unipolar_unit_f64;
 use numeric::limit_unipolar_unit_f64_to_u8;
-use numeric::pairsort_u8;
+use numeric::quantize_unit_f64;
 use range::BipolarDmxRangeMatrix;
 use range::BooleanDmxRangeMatrix;
 use range::DmxRange;
+use range::Range;
 use range::SpinDmxRangeMatrix;
 use range::UnipolarDmxRangeMatrix;
 
@@ -17,6 +29,56 @@ use range::UnipolarDmxRangeMatrix;
 // listens to 8bit subpixel values, just with a much larger 'universe size'
 // (per OPC channel).
 
+/// Temporal dithering: vary an 8-bit channel from frame to frame so that its
+/// average over a few frames lands between two steps. This smooths slow fades
+/// at the low end of a dimmer, where each step is a visible jump.
+#[deriving(Clone,PartialEq,Show)]
+pub enum DitherMode {
+    /// Carry each frame's rounding error into the next (first order error
+    /// diffusion). The most accurate average, with an irregular pattern.
+    ErrorDiffusion,
+    /// Compare against a repeating sequence of 8 thresholds. A regular
+    /// pattern, and a resolution of an eighth of a step.
+    OrderedDither,
+}
+
+/// Per-channel dithering state, carried across frames.
+#[deriving(Clone,PartialEq,Show)]
+pub struct DitherState {
+    pub error: f64,
+    pub frame: uint,
+}
+
+impl DitherState {
+    pub fn new() -> DitherState {
+        DitherState { error: 0.0, frame: 0 }
+    }
+}
+
+// Thresholds in bit-reversed order, so that any run of frames is spread
+// evenly over the step.
+static ORDERED_THRESHOLDS: [f64, ..8] = [0.0, 0.5, 0.25, 0.75, 0.125, 0.625, 0.375, 0.875];
+
+/// Quantize a unipolar value, clamped to [0.0..1.0], to a channel value for
+/// this frame, updating the dithering state.
+pub fn dither_unipolar_f64_to_u8(n: f64, mode: DitherMode, state: &mut DitherState) -> u8 {
+    let level = limit_unipolar_unit_f64(n) * 255.0;
+    let q = match mode {
+        ErrorDiffusion => {
+            let target = level + state.error;
+            let q = target.round().max(0.0).min(255.0);
+            state.error = target - q;
+            q
+        },
+        OrderedDither => {
+            let threshold = ORDERED_THRESHOLDS[state.frame % ORDERED_THRESHOLDS.len()];
+            (level + threshold).floor().min(255.0)
+        },
+    };
+    state.frame += 1;
+    q as u8
+}
+
 //// Write a single unipolar value to the Dmx channel at attribute.offset.
 //// Clip x to the range [0..1.0].
 pub fn render_dmx_float_unipolar(n: f64, offset: uint, buffer: &mut[u8]) -> u8 {
@@ -30,9 +92,9 @@ pub fn render_dmx_float_unipolar(n: f64, offset: uint, buffer: &mut[u8]) -> u8 {
 /// Assume x is a number in the range [-1.0..1.0].
 /// Out of range values are clipped to this range (for now).
 ///
-/// The mapping is linear. For example,
-///   x=0 maps to the channel value 127.
+/// The mapping is linear, in 256 bins of equal width. For example,
 ///   x=-1.0 maps to the channel value 0.
+///   x=0 maps to the channel value 128.
 ///   x=1.0 maps to the channel value 255.
 pub fn render_dmx_float_bipolar(n: f64, offset: uint, buffer: &mut[u8]) -> u8 {
     buffer[offset] = limit_bipolar_unit_f64_to_u8(n);
@@ -41,44 +103,36 @@ pub fn render_dmx_float_bipolar(n: f64, offset: uint, buffer: &mut[u8]) -> u8 {
 
 /// Write a single bipolar value to the Dmx channel at offset.
 /// Clip n to [-1.0..1.0].
-// TODO: test behavior of reverse ranges.
+///
+/// -1.0, 0.0 and 1.0 map to the first value in the min, mid and max rows.
+/// Values in between are spread evenly over the neg and pos rows, from each
+/// row's min towards its max.
 pub fn render_dmx_float_bipolar_with_range(n: f64,
         range: &BipolarDmxRangeMatrix, offset: uint, buffer: &mut[u8]) -> u8 {
 
     let nn = limit_bipolar_unit_f64(n);
     buffer[offset] =
-        if nn == 0.0 {
+        if nn <= -1.0 {
+            range.min.min
+        } else if nn < 0.0 {
+            render_open_interval(nn + 1.0, &range.neg)
+        } else if nn == 0.0 {
             // TODO consider adding some tolerance for the zero notch? Or perhaps
             // this should just be the responsibility of the UI.
             range.mid.min
-        } else if nn < 0.0 {
-            if nn <= -1.0 {
-                range.min.min
-            } else {
-                // nn is negative
-                // Invert the interpolation if needed.
-                let (rmin, rmax) = pairsort_u8(range.neg.min, range.neg.max);
-                // TODO: maximize precision
-                let delta = (rmax - rmin) as f64 + 0.999999;
-                // TODO verify rounding (see also below)
-                rmax + (nn * delta) as u8
-            }
+        } else if nn < 1.0 {
+            render_open_interval(nn, &range.pos)
         } else {
-            if nn >= -1.0 {
-                range.max.min
-            } else {
-                // nn is positive
-                let (rmin, rmax) = pairsort_u8(range.pos.min, range.pos.max);
-                // TODO maximize precision
-                let delta = (rmax - rmin) as f64 + 0.999999;
-                rmin + (nn * delta) as u8
-            }
+            range.max.min
         };
     buffer[offset]
 }
 
 /// Write a single unipolar value to the Dmx channel at offset.
 /// Clip n to the range [0.0..1.0].
+///
+/// 0.0 and 1.0 map to the first value in the min and max rows. Values in
+/// between are spread evenly over the mid row, from its min towards its max.
 // TODO add 'Unipolar' or 'Uni' to name
 pub fn render_dmx_float_unipolar_with_range(n: f64,
         range: &UnipolarDmxRangeMatrix, offset: uint, buffer: &mut[u8]) -> u8 {
@@ -90,40 +144,120 @@ pub fn render_dmx_float_unipolar_with_range(n: f64,
         } else if nn >= 1.0 {
             range.max.min
         } else {
-            // Invert the interpolation if needed.
-            let (rmin, rmax) = pairsort_u8(range.mid.min, range.mid.max);
-            // TODO: just a sketch; make this precise
-            let delta = (rmax - rmin) as f64 + 0.999999;
-            // TODO: verify distribution of values over u8 range after rounding
-            rmin + (nn * delta) as u8
+            render_open_interval(nn, &range.mid)
         };
     buffer[offset]
 }
 
-/// Write a single unipolar value to a contiguous pair of Dmx channels.
+/// Map n in the open interval (0.0..1.0) onto the channel values of r, which
+/// may be reversed.
+fn render_open_interval(n: f64, r: &DmxRange) -> u8 {
+    r.step(quantize_unit_f64(n, r.steps()))
+}
+
+/// Write a single unipolar value to a pair of Dmx channels, which need not be
+/// adjacent (see DmxAddressOffset).
 /// Clip n to the range [0..1.0].
 /// This is a big-endian implementation. HSB is written first, then LSB.
-// TODO little-endian equivalent
-pub fn render_dmx_double_big_endian(n: f64, offset: uint, buffer: &mut[u8])
-        -> (u8, u8) {
+/// See render_dmx_fine_unipolar for other byte orders, 24 bits, and ranges.
+pub fn render_dmx_double_big_endian(n: f64, offset: &DmxAddressOffset,
+        buffer: &mut[u8]) -> (u8, u8) {
 
-    let nn = limit_unipolar_unit_f64(n);
-    let (hsb, lsb) =
-        if nn <= 0.0 {
-            (0, 0)
-        } else if nn >= 1.0 {
-            (255, 255)
-        } else {
-            // TODO verify rounding
-            let almost_one = (nn * 65535.999999) as u16;
-            // TODO verify truncation
-            (((almost_one & 0xFF00) >> 8) as u8, (almost_one & 0xFF) as u8)
-        };
-    buffer[offset] = hsb;
-    buffer[offset+1] = lsb;
+    let v = quantize_unit_f64(n, 65536) as u16;
+    let (hsb, lsb) = ((v >> 8) as u8, (v & 0xFF) as u8);
+    buffer[offset.nth(0)] = hsb;
+    buffer[offset.nth(1)] = lsb;
     (hsb, lsb)
 }
 
+/// The order in which a multi-channel value's bytes are written.
+#[deriving(Clone,PartialEq,Show)]
+pub enum ByteOrder {
+    /// Most significant (coarse) byte first.
+    BigEndian,
+    /// Least significant (fine) byte first.
+    LittleEndian,
+}
+
+/// The layout of a value spread over several channels (coarse, fine, and
+/// for 24 bits, ultra fine), as written by the fine renderers.
+#[deriving(Clone,Show)]
+pub struct FineChannelFormat {
+    /// 2 for 16 bits, 3 for 24 bits.
+    pub bytes: uint,
+    pub order: ByteOrder,
+    /// The channel values of the ends of the attribute's range: 0.0 (or
+    /// -1.0 for bipolar values) at min and 1.0 at max. A reverse range
+    /// inverts the mapping. None uses every channel value, e.g. [0..65535].
+    pub range: Option<Range<u32>>,
+}
+
+impl FineChannelFormat {
+    /// The channel values at either end of the mapping.
+    pub fn span(&self) -> (u32, u32) {
+        match self.range {
+            Some(ref r) => (r.min, r.max),
+            None => (0, ((1u64 << (8 * self.bytes)) - 1) as u32),
+        }
+    }
+
+    /// The number of channel values in the mapping, inclusive.
+    pub fn steps(&self) -> uint {
+        let (lo, hi) = self.span();
+        if lo <= hi {
+            (hi - lo) as uint + 1
+        } else {
+            (lo - hi) as uint + 1
+        }
+    }
+
+    /// Write v as self.bytes channels, in order.
+    pub fn write(&self, v: u32, offset: &DmxAddressOffset, buffer: &mut[u8]) {
+        for i in range(0, self.bytes) {
+            let shift = match self.order {
+                BigEndian => 8 * (self.bytes - 1 - i),
+                LittleEndian => 8 * i,
+            };
+            buffer[offset.nth(i)] = (v >> shift) as u8;
+        }
+    }
+
+    /// Read a value written by write.
+    pub fn read(&self, offset: &DmxAddressOffset, buffer: &[u8]) -> u32 {
+        let mut v = 0u32;
+        for i in range(0, self.bytes) {
+            let shift = match self.order {
+                BigEndian => 8 * (self.bytes - 1 - i),
+                LittleEndian => 8 * i,
+            };
+            v = v | (buffer[offset.nth(i)] as u32 << shift);
+        }
+        v
+    }
+}
+
+/// Write a unipolar value over several Dmx channels, which need not be
+/// adjacent (see DmxAddressOffset), as format describes. Clip n to the
+/// range [0.0..1.0].
+pub fn render_dmx_fine_unipolar(n: f64, format: &FineChannelFormat,
+        offset: &DmxAddressOffset, buffer: &mut[u8]) -> u32 {
+
+    let (lo, hi) = format.span();
+    let i = quantize_unit_f64(n, format.steps()) as u32;
+    let v = if lo <= hi { lo + i } else { lo - i };
+    format.write(v, offset, buffer);
+    v
+}
+
+/// Write a bipolar value over several Dmx channels, as format describes.
+/// Clip n to the range [-1.0..1.0]. For example, with the full 16 bit
+/// range, -1.0 is 0, 0.0 is 32768 and 1.0 is 65535.
+pub fn render_dmx_fine_bipolar(n: f64, format: &FineChannelFormat,
+        offset: &DmxAddressOffset, buffer: &mut[u8]) -> u32 {
+
+    render_dmx_fine_unipolar((limit_bipolar_unit_f64(n) + 1.0) / 2.0, format, offset, buffer)
+}
+
 /// Interpret an integer index n as a Dmx channel value.
 ///
 /// attribute.range must be an nx2 sequence of channel value Ranges<u16>
@@ -137,17 +271,16 @@ pub fn render_dmx_double_big_endian(n: f64, offset: uint, buffer: &mut[u8])
 /// TODO: declare a type for [DmxRange]? DmxRangeVec?
 ///
 /// The parameter index must be a valid integer index into attribute.range.
-/// (For now, out of range indices revert to 0.)
+/// (For now, out of range indices, including negative ones, revert to 0.)
 // TODO for uniformity, make an enum for this kind of IndexedRangeMatrix? if so, move relevant docs into it.
 pub fn render_dmx_int_indexed_with_range(n: i64, range: &[DmxRange],
         offset: uint, buffer: &mut[u8]) -> u8 {
 
-    // TODO: deal with possibility of out-of-range n, for 32 bit systems
-    if n < range.len() as i64 {
+    if n >= 0 && (n as u64) < range.len() as u64 {
+        buffer[offset] = range[n as uint].min;
+    } else {
         // FUTURE throw exception if index is out of range?
         buffer[offset] = 0;
-    } else {
-        buffer[offset] = range[n as uint].min;
     }
     buffer[offset]
 }
@@ -172,9 +305,13 @@ pub fn render_dmx_boolean_with_range(n: bool, range: &BooleanDmxRangeMatrix,
 ///
 /// The incoming spin value n is a single float in the range [-1.0,1.0].
 ///
-/// Renders two channels. The first channel is mode, the next speed.
+/// Renders two channels. The first channel is mode, the second speed. They
+/// need not be adjacent (see DmxAddressOffset). Speed is quantized like a
+/// plain unipolar float, except that a moving spin never renders speed 0,
+/// which would read back as stopped.
 pub fn render_dmx_spin_bipolar_2ch_with_range(n: f64,
-        range: &SpinDmxRangeMatrix, offset: uint, buffer: &mut[u8]) -> (u8, u8) {
+        range: &SpinDmxRangeMatrix, offset: &DmxAddressOffset,
+        buffer: &mut[u8]) -> (u8, u8) {
 
     let nn = limit_bipolar_unit_f64(n);
     let (mode, speed) =
@@ -182,14 +319,12 @@ pub fn render_dmx_spin_bipolar_2ch_with_range(n: f64,
             (range.stop.min, 0)
             // TODO: customizable speed range, in case 1 is still or 254 is fastest.
         } else if nn > 0.0 { // forward
-            // TODO verify rounding
-            (range.fwd.min, (nn * 255.999999) as u8)
+            (range.fwd.min, limit_unipolar_unit_f64_to_u8(nn).max(1))
         } else { // reverse
-            (range.rev.min, (-1.0 * nn * 255.999999) as u8)
+            (range.rev.min, limit_unipolar_unit_f64_to_u8(-nn).max(1))
         };
-    buffer[offset] = mode;
-    // TODO: mapped channels, in case direction and rotation are noncontiguous!
-    buffer[offset + 1] = speed;
+    buffer[offset.nth(0)] = mode;
+    buffer[offset.nth(1)] = speed;
     (mode, speed)
 }
 
@@ -211,12 +346,15 @@ pub enum DmxAttributeRenderer {
         UnipolarDmxRangeMatrix
     ),
 
-    // TODO: render into two nonadjacent indices in the buffer... see ideas in
-    // DmxAddressOffset
-    DmxDoubleRenderer(fn(n: f64, offset: uint, buffer: &mut[u8]) -> (u8, u8)),
+    DmxDoubleRenderer(fn(n: f64, offset: &DmxAddressOffset, buffer: &mut[u8]) -> (u8, u8)),
+
+    DmxFineRenderer(
+        fn(n: f64, format: &FineChannelFormat, offset: &DmxAddressOffset, buffer: &mut[u8]) -> u32,
+        FineChannelFormat
+    ),
 
     DmxIntIndexedWithRangeRenderer(
-        fn(n: i64, range: Vec<DmxRange>, offset: uint, buffer: &mut[u8]) -> u8,
+        fn(n: i64, range: &[DmxRange], offset: uint, buffer: &mut[u8]) -> u8,
         Vec<DmxRange> // CSM: Not sure about what the ownership situation should be with DmxRange.
     ),
 
@@ -226,8 +364,43 @@ pub enum DmxAttributeRenderer {
     ),
 
     DmxSpinBipolar2ChWithRangeRenderer(
-        fn(n: f64, range: &SpinDmxRangeMatrix, offset: uint, buffer: &mut[u8]) -> (u8, u8),
+        fn(n: f64, range: &SpinDmxRangeMatrix, offset: &DmxAddressOffset, buffer: &mut[u8]) -> (u8, u8),
         SpinDmxRangeMatrix
     ),
 }
 
+
+impl DmxAttributeRenderer {
+    /// The number of channels (bytes) this renderer writes.
+    pub fn footprint(&self) -> uint {
+        match *self {
+            DmxDoubleRenderer(_) | DmxSpinBipolar2ChWithRangeRenderer(..) => 2,
+            DmxFineRenderer(_, ref format) => format.bytes,
+            _ => 1,
+        }
+    }
+}
+
+#[test]
+fn test_dither() {
+    // Over enough frames, a level 30% of the way from step 2 to step 3
+    // averages out within an eighth of a step.
+    let n = 2.3 / 255.0;
+    for mode in [ErrorDiffusion, OrderedDither].iter() {
+        let mut state = DitherState::new();
+        let frames = 80u;
+        let mut sum = 0u;
+        for _ in range(0, frames) {
+            let q = dither_unipolar_f64_to_u8(n, *mode, &mut state);
+            assert!(q == 2 || q == 3);
+            sum += q as uint;
+        }
+        let mean = sum as f64 / frames as f64;
+        assert!((mean - 2.3).abs() <= 0.125, "{} averaged {}", mode, mean);
+    }
+    // Whole steps don't dither.
+    let mut state = DitherState::new();
+    for _ in range(0u, 8) {
+        assert_eq!(dither_unipolar_f64_to_u8(1.0, OrderedDither, &mut state), 255);
+    }
+}
@@ -0,0 +1,247 @@
+//! Streaming ACN (ANSI E1.31) output for DmxUniverse frames.
+//!
+//! A SacnSender identifies itself by a CID (a UUID, fixed for the life of the
+//! source) and a human readable source name. Each universe it sends has a
+//! priority, which receivers use to choose between competing sources, and its
+//! own sequence number. Packets go to the universe's multicast group, to
+//! unicast receivers, or both.
+//!
+//! E1.31 universes are numbered 1 to 63999, and a DmxUniverse's id is used as
+//! its universe number. Call terminate before shutting down, so receivers
+//! release the universes immediately instead of waiting out their timeouts.
+
+use std::fmt;
+use std::io::IoError;
+use std::io::net::ip::Ipv4Addr;
+use std::io::net::ip::SocketAddr;
+use std::io::net::udp::UdpSocket;
+
+use dmx::DmxUniverse;
+
+/// The UDP port E1.31 receivers listen on.
+pub static SACN_PORT: u16 = 5568;
+
+/// The priority receivers assume when a source doesn't care.
+pub static DEFAULT_PRIORITY: u8 = 100;
+
+static ACN_PACKET_IDENTIFIER: &'static [u8] = b"ASC-E1.17\0\0\0";
+static VECTOR_ROOT_E131_DATA: u32 = 0x00000004;
+static VECTOR_E131_DATA_PACKET: u32 = 0x00000002;
+static VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+
+/// Framing layer option bit: this source is going away.
+static OPTION_STREAM_TERMINATED: u8 = 0x40;
+
+pub enum SacnError {
+    /// No route has been added for this universe id.
+    SacnNoRoute(u32),
+    /// The universe id is not in 1..63999.
+    InvalidUniverse(u32),
+    /// The priority is not in 0..200.
+    InvalidPriority(u8),
+    SacnIoError(IoError),
+}
+
+impl fmt::Show for SacnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SacnNoRoute(id) => write!(f, "no sACN route for universe {}", id),
+            InvalidUniverse(id) => write!(f, "{} is not an E1.31 universe (1-63999)", id),
+            InvalidPriority(p) => write!(f, "{} is not an E1.31 priority (0-200)", p),
+            SacnIoError(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Where, and how urgently, to send one universe.
+pub struct SacnRoute {
+    pub universe_id: u32,
+    /// 0 to 200. Receivers take the highest priority source for a universe.
+    pub priority: u8,
+    pub destinations: Vec<SocketAddr>,
+    sequence: u8,
+}
+
+pub struct SacnSender {
+    socket: UdpSocket,
+    pub cid: [u8, ..16],
+    pub source_name: String,
+    pub routes: Vec<SacnRoute>,
+}
+
+/// The multicast group receivers of a universe join: 239.255.hi.lo.
+pub fn multicast_destination(universe_id: u32) -> SocketAddr {
+    SocketAddr {
+        ip: Ipv4Addr(239, 255, ((universe_id >> 8) & 0xFF) as u8, (universe_id & 0xFF) as u8),
+        port: SACN_PORT,
+    }
+}
+
+impl SacnSender {
+    /// Bind a socket for sending. cid must be unique to this source and
+    /// should not change between runs.
+    pub fn bind(local: SocketAddr, cid: [u8, ..16], source_name: &str)
+            -> Result<SacnSender, IoError> {
+        let socket = try!(UdpSocket::bind(local));
+        Ok(SacnSender {
+            socket: socket,
+            cid: cid,
+            source_name: source_name.to_string(),
+            routes: Vec::new(),
+        })
+    }
+
+    /// Send universe_id to its multicast group.
+    pub fn add_multicast_route(&mut self, universe_id: u32, priority: u8) -> Result<(), SacnError> {
+        self.add_route(universe_id, priority, vec!(multicast_destination(universe_id)))
+    }
+
+    /// Send universe_id to the given destinations, which may include its
+    /// multicast group. Replaces any existing route for the universe.
+    pub fn add_route(&mut self, universe_id: u32, priority: u8,
+            destinations: Vec<SocketAddr>) -> Result<(), SacnError> {
+        if universe_id < 1 || universe_id > 63999 {
+            return Err(InvalidUniverse(universe_id));
+        }
+        if priority > 200 {
+            return Err(InvalidPriority(priority));
+        }
+        self.routes.retain(|r| r.universe_id != universe_id);
+        self.routes.push(SacnRoute {
+            universe_id: universe_id,
+            priority: priority,
+            destinations: destinations,
+            sequence: 0,
+        });
+        Ok(())
+    }
+
+    /// Transmit a rendered universe frame as an E1.31 data packet to each of
+    /// its route's destinations.
+    pub fn send(&mut self, univ: &DmxUniverse) -> Result<(), SacnError> {
+        let i = match self.routes.iter().position(|r| r.universe_id == univ.id) {
+            Some(i) => i,
+            None => return Err(SacnNoRoute(univ.id)),
+        };
+        self.send_route(i, univ.frame.as_slice(), 0)
+    }
+
+    /// Tell every receiver that this source is going away. E1.31 asks for
+    /// three stream-terminated packets per universe, in case of loss.
+    pub fn terminate(&mut self) -> Result<(), SacnError> {
+        for i in range(0, self.routes.len()) {
+            for _ in range(0u, 3) {
+                try!(self.send_route(i, [], OPTION_STREAM_TERMINATED));
+            }
+        }
+        Ok(())
+    }
+
+    fn send_route(&mut self, i: uint, data: &[u8], options: u8) -> Result<(), SacnError> {
+        let route = self.routes.get_mut(i);
+        route.sequence += 1; // wraps, as E1.31 expects
+        let packet = e131_data_packet(&self.cid, self.source_name.as_slice(),
+            route.priority, route.sequence, options, route.universe_id as u16, data);
+        for dest in route.destinations.iter() {
+            match self.socket.sendto(packet.as_slice(), *dest) {
+                Ok(_) => (),
+                Err(e) => return Err(SacnIoError(e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn push_u16(p: &mut Vec<u8>, n: u16) {
+    p.push((n >> 8) as u8);
+    p.push((n & 0xFF) as u8);
+}
+
+fn push_u32(p: &mut Vec<u8>, n: u32) {
+    push_u16(p, (n >> 16) as u16);
+    push_u16(p, (n & 0xFFFF) as u16);
+}
+
+/// Each layer opens with 4 bits of flags (always 0x7) and a 12-bit length,
+/// counted from the start of the layer to the end of the packet.
+fn flags_and_length(packet_len: uint, layer_start: uint) -> u16 {
+    0x7000 | (packet_len - layer_start) as u16
+}
+
+/// Build an E1.31 data packet: root, framing and DMP layers, followed by the
+/// null start code and up to 512 slots of data. All fields are big-endian.
+pub fn e131_data_packet(cid: &[u8, ..16], source_name: &str, priority: u8,
+        sequence: u8, options: u8, universe: u16, data: &[u8]) -> Vec<u8> {
+
+    let len = 126 + data.len();
+    let mut p = Vec::with_capacity(len);
+
+    // Root layer
+    push_u16(&mut p, 0x0010); // preamble size
+    push_u16(&mut p, 0x0000); // postamble size
+    p.push_all(ACN_PACKET_IDENTIFIER);
+    push_u16(&mut p, flags_and_length(len, 16));
+    push_u32(&mut p, VECTOR_ROOT_E131_DATA);
+    p.push_all(cid.as_slice());
+
+    // Framing layer
+    push_u16(&mut p, flags_and_length(len, 38));
+    push_u32(&mut p, VECTOR_E131_DATA_PACKET);
+    let name = source_name.as_bytes();
+    let n = if name.len() < 64 { name.len() } else { 63 }; // NUL-terminated
+    p.push_all(name.slice_to(n));
+    p.grow(64 - n, &0u8);
+    p.push(priority);
+    push_u16(&mut p, 0); // synchronization address: none
+    p.push(sequence);
+    p.push(options);
+    push_u16(&mut p, universe);
+
+    // DMP layer
+    push_u16(&mut p, flags_and_length(len, 115));
+    p.push(VECTOR_DMP_SET_PROPERTY);
+    p.push(0xa1); // address type and data type
+    push_u16(&mut p, 0x0000); // first property address
+    push_u16(&mut p, 0x0001); // address increment
+    push_u16(&mut p, 1 + data.len() as u16); // property value count
+    p.push(0x00); // DMX512 null start code
+    p.push_all(data);
+    p
+}
+
+#[test]
+fn test_sacn_to_local_listener() {
+    // Port 0 lets the OS pick free ports.
+    let mut listener = UdpSocket::bind(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 }).unwrap();
+    let listen_addr = listener.socket_name().unwrap();
+    let cid = [0x5au8, ..16];
+    let mut sender = SacnSender::bind(SocketAddr { ip: Ipv4Addr(127, 0, 0, 1), port: 0 },
+        cid, "sinuous").unwrap();
+
+    assert!(sender.add_route(0, 100, vec!(listen_addr)).is_err());
+    assert!(sender.add_route(1, 201, vec!(listen_addr)).is_err());
+    sender.add_route(0x0102, 150, vec!(listen_addr)).ok().unwrap();
+    assert_eq!(multicast_destination(0x0102).ip, Ipv4Addr(239, 255, 1, 2));
+
+    let mut univ = DmxUniverse { id: 0x0102, name: "U".to_string(), frame: [0, ..512] };
+    univ.frame[0] = 255;
+    sender.send(&univ).ok().unwrap();
+
+    let mut buf = [0u8, ..1024];
+    let (len, _) = listener.recvfrom(&mut buf).unwrap();
+    assert_eq!(len, 638);
+    assert_eq!(buf.slice(4, 16), ACN_PACKET_IDENTIFIER);
+    assert_eq!((buf[16], buf[17]), (0x72, 0x6e)); // 638 - 16 = 622
+    assert_eq!(buf.slice(22, 38), cid.as_slice());
+    assert_eq!(buf.slice(44, 51), b"sinuous");
+    assert_eq!(buf[108], 150);
+    assert_eq!(buf[111], 1);
+    assert_eq!((buf[113], buf[114]), (0x01, 0x02));
+    assert_eq!((buf[123], buf[124]), (0x02, 0x01)); // 513 property values
+    assert_eq!(buf[126], 255);
+
+    sender.terminate().ok().unwrap();
+    let (_, _) = listener.recvfrom(&mut buf).unwrap();
+    assert_eq!(buf[111], 2);
+    assert_eq!(buf[112], OPTION_STREAM_TERMINATED);
+}
@@ -31,17 +31,32 @@ extern crate time;
 
 use test_dimmer::*; // TODO: figure out how to move test modules to a subdirectory
 
+mod aim;
+mod artnet;
 mod blend;
+mod color;
+mod cue;
+mod curve;
+mod decode;
 mod device;
 mod dmx;
 mod effect;
+mod generator;
+mod json;
+mod loader;
 mod mixer;
 mod numeric;
+mod opc;
+mod patcher;
+mod pixmap;
 mod profile;
 mod range;
 mod render;
+mod sacn;
+mod spatial;
 mod test_dimmer;
 mod topo;
+mod validate;
 mod world;
 
 
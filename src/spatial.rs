@@ -0,0 +1,176 @@
+//! Spatial generators: effects computed from where devices hang, rather than
+//! from their order in the patch.
+//!
+//! A SpatialField assigns a level in [-1.0..1.0] to every point in the world
+//! at every moment. A SpatialGenerator samples a field at each of its targets'
+//! positions, usually taken from the Locs of their DevicePatches, and writes
+//! the scaled level into the target endpoint. Positions are in whatever units
+//! the rig was measured in (say meters), and speeds are in units per second.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use device::*;
+use generator::Waveform;
+use generator::fit_to_topo;
+use generator::hash_u64;
+use generator::hash_unit;
+use generator::wave;
+use mixer::AnimatorPlugin;
+use mixer::Timepoint;
+use world::Position;
+
+/// Parallel wavefronts sweeping along direction: a one dimensional Lfo
+/// stretched across space.
+pub struct PlanarWave {
+    pub waveform: Waveform,
+    pub direction: Position,
+    /// The distance between wavefronts.
+    pub wavelength: f64,
+    pub speed: f64,
+}
+
+/// Concentric wavefronts moving out from center (or in, if speed is
+/// negative).
+pub struct RadialPulse {
+    pub waveform: Waveform,
+    pub center: Position,
+    pub wavelength: f64,
+    pub speed: f64,
+}
+
+/// Smooth 3D value noise, varying over distances of about scale, and drifting
+/// through the rig with velocity.
+pub struct ValueNoise {
+    pub seed: u64,
+    pub scale: f64,
+    pub velocity: Position,
+}
+
+pub enum SpatialField {
+    PlanarWaveField(PlanarWave),
+    RadialPulseField(RadialPulse),
+    NoiseField(ValueNoise),
+}
+
+impl SpatialField {
+    /// The field's level at point p at time.
+    pub fn sample(&self, p: &Position, time: &Timepoint) -> f64 {
+        let t = time.scene_ns as f64 / 1e9;
+        match *self {
+            PlanarWaveField(ref w) => {
+                let d = p.dot(&w.direction.normalize());
+                wave(w.waveform, (d - w.speed * t) / w.wavelength, 0)
+            },
+            RadialPulseField(ref r) => {
+                let d = p.distance(&r.center);
+                wave(r.waveform, (d - r.speed * t) / r.wavelength, 0)
+            },
+            NoiseField(ref n) => {
+                noise3(n.seed,
+                    (p.x - n.velocity.x * t) / n.scale,
+                    (p.y - n.velocity.y * t) / n.scale,
+                    (p.z - n.velocity.z * t) / n.scale)
+            },
+        }
+    }
+}
+
+/// 3D value noise in [-1.0..1.0]: random levels at the integer lattice
+/// points, smoothly interpolated between them.
+pub fn noise3(seed: u64, x: f64, y: f64, z: f64) -> f64 {
+    fn smooth(t: f64) -> f64 { t * t * (3.0 - 2.0 * t) }
+    fn lattice(seed: u64, x: i64, y: i64, z: i64) -> f64 {
+        hash_unit(hash_u64(hash_u64(seed, x), y), z)
+    }
+    fn lerp(a: f64, b: f64, t: f64) -> f64 { a + t * (b - a) }
+
+    let (xf, yf, zf) = (x.floor(), y.floor(), z.floor());
+    let (xi, yi, zi) = (xf as i64, yf as i64, zf as i64);
+    let (u, v, w) = (smooth(x - xf), smooth(y - yf), smooth(z - zf));
+    let plane = |z: i64| {
+        lerp(lerp(lattice(seed, xi, yi, z), lattice(seed, xi + 1, yi, z), u),
+             lerp(lattice(seed, xi, yi + 1, z), lattice(seed, xi + 1, yi + 1, z), u),
+             v)
+    };
+    lerp(plane(zi), plane(zi + 1), w)
+}
+
+/// The position of a device: the centroid of the Locs of all its patches, or
+/// None if it has not been located.
+pub fn device_position(device: &Device) -> Option<Position> {
+    let mut sum = Position::new(0.0, 0.0, 0.0);
+    let mut count = 0u;
+    for patch in device.patches.iter() {
+        for loc in patch.locs.iter() {
+            sum = Position::new(sum.x + loc.position.x, sum.y + loc.position.y,
+                sum.z + loc.position.z);
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return None;
+    }
+    let n = count as f64;
+    Some(Position::new(sum.x / n, sum.y / n, sum.z / n))
+}
+
+/// An endpoint driven by a SpatialGenerator, and where it is.
+pub struct SpatialTarget {
+    /// The path to the endpoint within the layer's scene.
+    pub path: DeviceTreePath,
+    pub position: Position,
+}
+
+/// Drive a group of endpoints by sampling a field at their positions, yielding
+/// offset + amplitude * level.
+pub struct SpatialGenerator {
+    pub field: SpatialField,
+    pub amplitude: f64,
+    pub offset: f64,
+    pub targets: Vec<SpatialTarget>,
+}
+
+impl AnimatorPlugin for SpatialGenerator {
+    fn animate(&mut self, time: &Timepoint, scene: &Rc<RefCell<DeviceTree>>,
+            _frame: Option<&Rc<RefCell<DeviceTree>>>) {
+        for target in self.targets.iter() {
+            let level = self.offset + self.amplitude * self.field.sample(&target.position, time);
+            with_endpoint(scene, target.path.as_slice(), |e, a| e.set_val(fit_to_topo(a, level)));
+        }
+    }
+}
+
+#[test]
+fn test_spatial_fields() {
+    use generator::Saw;
+    use test_dimmer::{at, close};
+
+    let t0 = at(0);
+    let t1 = at(1000000000);
+
+    // A saw sweeping along x at 1 unit/s: what is at x at time 0 is at x + 1
+    // a second later, whatever y and z.
+    let planar = PlanarWaveField(PlanarWave { waveform: Saw,
+        direction: Position::new(2.0, 0.0, 0.0), wavelength: 4.0, speed: 1.0 });
+    let a = planar.sample(&Position::new(1.0, 0.0, 0.0), &t0);
+    assert!(close(a, -0.5));
+    assert!(close(planar.sample(&Position::new(2.0, 5.0, -3.0), &t1), a));
+
+    // Points equidistant from the center see the same level.
+    let radial = RadialPulseField(RadialPulse { waveform: Saw,
+        center: Position::new(1.0, 1.0, 0.0), wavelength: 2.0, speed: 1.0 });
+    assert!(close(radial.sample(&Position::new(1.0, 2.0, 0.0), &t0),
+        radial.sample(&Position::new(0.0, 1.0, 0.0), &t0)));
+
+    // Noise is bounded, matches the lattice at integer points, and drifts.
+    let noise = NoiseField(ValueNoise { seed: 3, scale: 1.0,
+        velocity: Position::new(0.0, 0.0, 1.0) });
+    for i in range(0i, 50) {
+        let v = noise.sample(&Position::new(i as f64 * 0.31, 0.7, i as f64 * 0.13), &t0);
+        assert!(v >= -1.0 && v <= 1.0);
+    }
+    assert!(close(noise3(3, 2.0, 1.0, 0.0), hash_unit(hash_u64(hash_u64(3, 2), 1), 0)));
+    assert!(close(noise.sample(&Position::new(0.5, 0.5, 1.5), &t1),
+        noise.sample(&Position::new(0.5, 0.5, 0.5), &t0)));
+}
@@ -9,6 +9,8 @@ use time::*;
 use device::*;
 use dmx::*;
 use effect::*;
+use loader::load_profile;
+use mixer::Timepoint;
 use numeric::limit_unipolar_unit_f64_to_u8;
 use profile::*;
 use render::*;
@@ -27,6 +29,7 @@ pub fn create_dimmer() {
         date: "June 7, 2014".to_string(),
         version: 0,
         chan_alloc: DmxChannelCount(1),
+        yoke: None,
         root: Rc::new(RefCell::new(ProfileGraphAttribute(Attribute {
             name: "Dimmer".to_string(),
             nickname: "Dim".to_string(),
@@ -35,8 +38,11 @@ pub fn create_dimmer() {
             default: Some(Continuous(0.)),
             dmx: Some(DmxMap{
                 offset: DmxAddressOffsetSingle(0),
-                renderer: DmxFloatRenderer(render_dmx_float_unipolar)
-            })
+                renderer: DmxFloatRenderer(render_dmx_float_unipolar),
+                curve: None,
+                dither: None,
+            }),
+            wheel: None,
         })))
     };
 
@@ -78,7 +84,7 @@ pub fn create_dimmer() {
         let mut i: uint = 0;
         for d in devices.mut_iter() {
             write_dimmer_val(d,v);
-            d.render();
+            assert!(d.render(AbortOnFault).is_ok());
 
             // This assertion adds about +25% to the runtime of this test:
             // TODO: fn, method or macro to deboilerplatify these contortions:
@@ -105,6 +111,69 @@ pub fn create_dimmer() {
         writes_per_dmx_frame as u64, universes_per_dmx_frame as u64);
 }
 
+/// A fixture for tests: load a profile whose root is a branch of unipolar
+/// attributes on consecutive channels, each given as (name, nickname, effect
+/// type, colorspace) and defaulting to 0.0.
+pub fn load_branch_profile(name: &str, nickname: &str,
+        attributes: &[(&str, &str, &str, &str)]) -> Profile {
+    let children: Vec<String> = attributes.iter().enumerate().map(|(i, &(n, nick, t, cs))| {
+        format!(r#"{{"attribute": "{}", "nickname": "{}",
+            "effect": ["{}", "{}", "Value"],
+            "topo": "continuous_euclidian_unipolar", "default": 0.0,
+            "dmx": {{"offset": {}, "renderer": "float_unipolar"}}}}"#, n, nick, t, cs, i)
+    }).collect();
+    let src = format!(r#"{{"name": "{}", "nickname": "{}", "channels": {},
+        "root": {{"branch": "{}", "nickname": "{}", "children": [{}]}}}}"#,
+        name, nickname, attributes.len(), name, nickname, children.as_slice().connect(", "));
+    load_profile(src.as_slice()).ok().unwrap()
+}
+
+/// Two dimmers, Dim1 and Dim2, at channels 0 and 1.
+pub fn dimmer_pair() -> Profile {
+    load_branch_profile("Dimmer pair", "Dim2", [
+        ("Dimmer 1", "Dim1", "Dimmer", "ColorspaceI"),
+        ("Dimmer 2", "Dim2", "Dimmer", "ColorspaceI")])
+}
+
+/// An RGB pixel at channels 0 through 2.
+pub fn rgb_pixel() -> Profile {
+    load_branch_profile("RGB pixel", "Px", [
+        ("Red", "R", "Color", "ColorspaceRgb"),
+        ("Green", "G", "Color", "ColorspaceRgb"),
+        ("Blue", "B", "Color", "ColorspaceRgb")])
+}
+
+/// An empty device tree to patch test devices into.
+pub fn new_tree_root() -> Rc<RefCell<DeviceBranch>> {
+    Rc::new(RefCell::new(DeviceBranch { profile_branch: None, children: Vec::new() }))
+}
+
+/// The continuous value at path in scene. Fail if there is none.
+pub fn continuous_at(scene: &Rc<RefCell<DeviceTree>>, path: &[uint]) -> f64 {
+    let mut v = None;
+    with_endpoint(scene, path, |e, _| match e.get_val() {
+        Some(Continuous(x)) => v = Some(x),
+        _ => ()
+    });
+    v.expect("expected a continuous value")
+}
+
+/// True if a and b are equal but for rounding error.
+pub fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+/// True if a and b are the same length, and each pair of their elements
+/// differs by less than tolerance.
+pub fn all_within(a: &[f64], b: &[f64], tolerance: f64) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| (*x - *y).abs() < tolerance)
+}
+
+/// The first frame's Timepoint, scene_ns into the scene.
+pub fn at(scene_ns: u64) -> Timepoint {
+    Timepoint { scene_ns: scene_ns, system_ns: 0, frame_ct: 0 }
+}
+
 #[test]
 pub fn test_dimmer() {
     create_dimmer();
@@ -24,60 +24,105 @@ use blend::iblend_euclid_add;
 use blend::iblend_ring_subtract;
 use blend::iblend_euclid_subtract;
 
+use blend::fblend_euclid_mix;
+use blend::fblend_ring_uni_mix;
+use blend::fblend_ring_bi_mix;
+use blend::iblend_euclid_mix;
+use blend::iblend_ring_mix;
+use blend::iblend_snap_mix;
+
+use profile::AttributeValue;
+use profile::Continuous;
+use profile::Discrete;
+use range::Range;
+
 /// A function that blends two floating-point values and returns the result.
-type Blendf = fn(a:f64, b:f64) -> f64;
+pub type Blendf = fn(a:f64, b:f64) -> f64;
 
 /// A function that blends two integer values and returns the result.
-type Blendi = fn(a:i64, b:i64) -> i64;
+pub type Blendi = fn(a:i64, b:i64) -> i64;
 // TODO remember why I decided that range was essential with these, or simplify.
 // Maybe every int blender should take a range, for uniformity.
 /// A function that blends two integer values and an integer range, returning
 /// the result.
-type BlendRangedi = fn(a:i64, b:i64, minimum: i64, maximum: i64) -> i64;
+pub type BlendRangedi = fn(a:i64, b:i64, minimum: i64, maximum: i64) -> i64;
+
+/// A function that mixes two floating-point values by a weight in [0.0..1.0].
+pub type Mixf = fn(a: f64, b: f64, alpha: f64) -> f64;
+
+/// A function that mixes two integer values in a range by a weight in
+/// [0.0..1.0].
+pub type MixRangedi = fn(a: i64, b: i64, alpha: f64, minimum: i64, maximum: i64) -> i64;
 
 /// Map each high-level aesthetic blending intent to a specific implementation.
-struct ContinuousBlenderTable {
-    clobber: Blendf,
-    max: Blendf,
-    min: Blendf,
-    median: Blendf,
-    add: Blendf,
-    subtract: Blendf,
-    add_modulus: Blendf,
-    subtract_modulus: Blendf,
-    multiply: Blendf,
-    abs_max: Blendf,
-    abs_min: Blendf,
+pub struct ContinuousBlenderTable {
+    pub clobber: Blendf,
+    pub max: Blendf,
+    pub min: Blendf,
+    pub median: Blendf,
+    pub add: Blendf,
+    pub subtract: Blendf,
+    pub add_modulus: Blendf,
+    pub subtract_modulus: Blendf,
+    pub multiply: Blendf,
+    pub abs_max: Blendf,
+    pub abs_min: Blendf,
+    pub mix: Mixf,
 }
 
-struct DiscreteBlenderTable {
-    clobber: Blendi,
-    max: Blendi,
-    min: Blendi,
-    median: BlendRangedi,
-    add: BlendRangedi,
-    subtract: BlendRangedi,
-    add_modulus: BlendRangedi,
-    subtract_modulus: BlendRangedi,
-    multiply: Blendi,
-    abs_max: Blendi,
-    abs_min: Blendi,
+pub struct DiscreteBlenderTable {
+    pub clobber: Blendi,
+    pub max: Blendi,
+    pub min: Blendi,
+    pub median: BlendRangedi,
+    pub add: BlendRangedi,
+    pub subtract: BlendRangedi,
+    pub add_modulus: BlendRangedi,
+    pub subtract_modulus: BlendRangedi,
+    pub multiply: Blendi,
+    pub abs_max: Blendi,
+    pub abs_min: Blendi,
+    pub mix: MixRangedi,
 }
 
 /// A static lookup table mapping high-level (aesthetic) blend modes to
 /// specific implementations.
-enum BlenderTable {
+pub enum BlenderTable {
     // there must be a less awkward way to do this...
     ContinuousBlenders(ContinuousBlenderTable),
     DiscreteBlenders(DiscreteBlenderTable),
 }
 
+/// A high-level, aesthetic blending intent. Each topology maps each mode to
+/// the specific blender that means the same thing for its values.
+#[deriving(Show,Clone,PartialEq)]
+pub enum BlendMode {
+    BlendClobber,
+    BlendMax,
+    BlendMin,
+    BlendMedian,
+    BlendAdd,
+    BlendSubtract,
+    BlendAddModulus,
+    BlendSubtractModulus,
+    BlendMultiply,
+    BlendAbsMax,
+    BlendAbsMin,
+}
+
 /// Topology descriptors constrain and shape the parametric space for all
 /// attribute values.
 pub struct Topo {
     /// Quasi-continuous (encoded as f64) or discrete (encoded as i64).
     continuous: bool,
 
+    /// Continuous values center on zero, with range [-1.0,1.0] rather than
+    /// [0.0,1.0]. Always false for discrete topologies.
+    bipolar: bool,
+
+    /// Values wrap around from the maximum to the minimum.
+    ring: bool,
+
     /// Hint: interpolation aesthetically encouraged.
     blend_encouraged: bool,
 
@@ -85,8 +130,98 @@ pub struct Topo {
     blend_meaningful: bool,
 
     /// Blender functions appropriate to blending pairs of values with this
-    /// topology.
-    blenders: BlenderTable,
+    /// topology. See Topo::blend.
+    pub blenders: BlenderTable,
+}
+
+impl Topo {
+    /// True if values are encoded as Continuous(f64), false for Discrete(i64).
+    pub fn is_continuous(&self) -> bool {
+        self.continuous
+    }
+
+    /// True if continuous values range over [-1.0,1.0] rather than [0.0,1.0].
+    pub fn is_bipolar(&self) -> bool {
+        self.bipolar
+    }
+
+    /// True if values wrap around, like angles of rotation.
+    pub fn is_ring(&self) -> bool {
+        self.ring
+    }
+
+    /// Blend a (on top) with b using this topology's blender for mode. The
+    /// discrete median, add and subtract blenders (and their modulus
+    /// variants) also need the range of valid values; without one, they
+    /// clobber. Return None if a and b aren't both of the kind of value this
+    /// topology holds.
+    pub fn blend(&self, mode: BlendMode, a: AttributeValue, b: AttributeValue,
+            range: Option<&Range<i64>>) -> Option<AttributeValue> {
+        match (&self.blenders, a, b) {
+            (&ContinuousBlenders(ref t), Continuous(x), Continuous(y)) => {
+                let f = match mode {
+                    BlendClobber => t.clobber,
+                    BlendMax => t.max,
+                    BlendMin => t.min,
+                    BlendMedian => t.median,
+                    BlendAdd => t.add,
+                    BlendSubtract => t.subtract,
+                    BlendAddModulus => t.add_modulus,
+                    BlendSubtractModulus => t.subtract_modulus,
+                    BlendMultiply => t.multiply,
+                    BlendAbsMax => t.abs_max,
+                    BlendAbsMin => t.abs_min,
+                };
+                Some(Continuous(f(x, y)))
+            },
+            (&DiscreteBlenders(ref t), Discrete(x), Discrete(y)) => {
+                let ranged = match mode {
+                    BlendMedian => Some(t.median),
+                    BlendAdd => Some(t.add),
+                    BlendSubtract => Some(t.subtract),
+                    BlendAddModulus => Some(t.add_modulus),
+                    BlendSubtractModulus => Some(t.subtract_modulus),
+                    _ => None
+                };
+                let n = match (ranged, range) {
+                    (Some(f), Some(r)) => f(x, y, r.min, r.max),
+                    (Some(_), None) => (t.clobber)(x, y),
+                    (None, _) => {
+                        let f = match mode {
+                            BlendMax => t.max,
+                            BlendMin => t.min,
+                            BlendMultiply => t.multiply,
+                            BlendAbsMax => t.abs_max,
+                            BlendAbsMin => t.abs_min,
+                            _ => t.clobber,
+                        };
+                        f(x, y)
+                    },
+                };
+                Some(Discrete(n))
+            },
+            _ => None
+        }
+    }
+
+    /// Mix from b toward a by alpha in [0.0..1.0]: linearly for Euclidian
+    /// topologies, the short way around for rings, and by snapping halfway
+    /// for sets, where interpolation is forbidden. Discrete ring and array
+    /// mixes need the range of valid values; without one, they snap too.
+    /// Return None if a and b aren't both of the kind of value this topology
+    /// holds.
+    pub fn mix(&self, a: AttributeValue, b: AttributeValue, alpha: f64,
+            range: Option<&Range<i64>>) -> Option<AttributeValue> {
+        match (&self.blenders, a, b) {
+            (&ContinuousBlenders(ref t), Continuous(x), Continuous(y)) =>
+                Some(Continuous((t.mix)(x, y, alpha))),
+            (&DiscreteBlenders(ref t), Discrete(x), Discrete(y)) => match range {
+                Some(r) => Some(Discrete((t.mix)(x, y, alpha, r.min, r.max))),
+                None => Some(Discrete(iblend_snap_mix(x, y, alpha, 0, 0))),
+            },
+            _ => None
+        }
+    }
 }
 
 /// Naturally continuous, values bounded, interpolation recommended.
@@ -95,6 +230,8 @@ pub struct Topo {
 /// Example: dimmer
 pub static continuous_euclidian_unipolar: Topo = Topo {
     continuous: true,
+    bipolar: false,
+    ring: false,
     blend_encouraged: true,
     blend_meaningful: true,
     blenders: ContinuousBlenders(ContinuousBlenderTable {
@@ -109,6 +246,7 @@ pub static continuous_euclidian_unipolar: Topo = Topo {
         multiply: fblend_euclid_multiply,
         abs_max: fblend_euclid_max,
         abs_min: fblend_euclid_min,
+        mix: fblend_euclid_mix,
     })
 };
 
@@ -118,6 +256,8 @@ pub static continuous_euclidian_unipolar: Topo = Topo {
 /// Example: X- or Y-position on a bounded pivot or linear track
 pub static continuous_euclidian_bipolar: Topo = Topo {
     continuous: true,
+    bipolar: true,
+    ring: false,
     blend_encouraged: true,
     blend_meaningful: true,
     blenders: ContinuousBlenders(ContinuousBlenderTable {
@@ -132,6 +272,7 @@ pub static continuous_euclidian_bipolar: Topo = Topo {
         multiply: fblend_euclid_multiply,
         abs_max: fblend_euclid_max,
         abs_min: fblend_euclid_min,
+        mix: fblend_euclid_mix,
     })
 };
 
@@ -141,6 +282,8 @@ pub static continuous_euclidian_bipolar: Topo = Topo {
 /// Example: angle of rotation
 pub static continuous_ring_unipolar: Topo = Topo {
     continuous: true,
+    bipolar: false,
+    ring: true,
     blend_encouraged: true,
     blend_meaningful: true,
     blenders: ContinuousBlenders(ContinuousBlenderTable {
@@ -155,6 +298,7 @@ pub static continuous_ring_unipolar: Topo = Topo {
         multiply: fblend_euclid_multiply,
         abs_max: fblend_euclid_max,
         abs_min: fblend_euclid_min,
+        mix: fblend_ring_uni_mix,
     })
 };
 
@@ -164,6 +308,8 @@ pub static continuous_ring_unipolar: Topo = Topo {
 /// Example: fully commutated pan or tilt
 pub static continuous_ring_bipolar: Topo = Topo {
     continuous: true,
+    bipolar: true,
+    ring: true,
     blend_encouraged: true,
     blend_meaningful: true,
     blenders: ContinuousBlenders(ContinuousBlenderTable {
@@ -178,6 +324,7 @@ pub static continuous_ring_bipolar: Topo = Topo {
         multiply: fblend_euclid_multiply,
         abs_max: fblend_euclid_max,
         abs_min: fblend_euclid_min,
+        mix: fblend_ring_bi_mix,
     })
 };
 
@@ -195,6 +342,8 @@ fn iblend_todo(a: i64, _b: i64) -> i64 {
 /// Example: litho index
 pub static discrete_ring: Topo = Topo {
     continuous: false,
+    bipolar: false,
+    ring: true,
     blend_encouraged: false,
     blend_meaningful: true,
     blenders: DiscreteBlenders(DiscreteBlenderTable {
@@ -209,6 +358,7 @@ pub static discrete_ring: Topo = Topo {
         multiply: iblend_todo,
         abs_max: iblend_euclid_max, // or _abs_max if we don't want to restrict range >= 0
         abs_min: iblend_euclid_min, // ditto
+        mix: iblend_ring_mix,
     })
 };
 
@@ -218,6 +368,8 @@ pub static discrete_ring: Topo = Topo {
 /// Example: linear 35mm slide tray index
 pub static discrete_array: Topo = Topo {
     continuous: false,
+    bipolar: false,
+    ring: false,
     blend_encouraged: false,
     blend_meaningful: true,
     blenders: DiscreteBlenders(DiscreteBlenderTable {
@@ -232,6 +384,7 @@ pub static discrete_array: Topo = Topo {
         multiply: iblend_todo,
         abs_max: iblend_euclid_max, // or _abs_max if we don't want to restrict range >= 0
         abs_min: iblend_euclid_min, // ditto
+        mix: iblend_euclid_mix,
     })
 };
 
@@ -244,6 +397,8 @@ pub static discrete_array: Topo = Topo {
 /// Example: color wheel mode
 pub static discrete_set: Topo = Topo {
     continuous: false,
+    bipolar: false,
+    ring: false,
     blend_encouraged: false,
     blend_meaningful: false,
     blenders: DiscreteBlenders(DiscreteBlenderTable {
@@ -259,5 +414,60 @@ pub static discrete_set: Topo = Topo {
         multiply: iblend_todo,
         abs_max: iblend_euclid_max, // or _abs_max if we don't want to restrict range >= 0
         abs_min: iblend_euclid_min, // ditto
+        mix: iblend_snap_mix,
     })
 };
+
+/// Look up one of the static topologies above by its name, e.g. for loading
+/// profiles from a file.
+pub fn topo_by_name(name: &str) -> Option<&'static Topo> {
+    match name {
+        "continuous_euclidian_unipolar" => Some(&continuous_euclidian_unipolar),
+        "continuous_euclidian_bipolar" => Some(&continuous_euclidian_bipolar),
+        "continuous_ring_unipolar" => Some(&continuous_ring_unipolar),
+        "continuous_ring_bipolar" => Some(&continuous_ring_bipolar),
+        "discrete_ring" => Some(&discrete_ring),
+        "discrete_array" => Some(&discrete_array),
+        "discrete_set" => Some(&discrete_set),
+        _ => None
+    }
+}
+
+#[test]
+fn test_blend_modes() {
+    let r = Range { min: 0i64, max: 7 };
+    match discrete_ring.blend(BlendAdd, Discrete(6), Discrete(3), Some(&r)) {
+        Some(Discrete(1)) => (),
+        _ => fail!("expected the sum to wrap around to 1")
+    }
+    match discrete_array.blend(BlendAdd, Discrete(6), Discrete(3), Some(&r)) {
+        Some(Discrete(7)) => (),
+        _ => fail!("expected the sum to be limited to 7")
+    }
+    match continuous_euclidian_unipolar.blend(BlendMax, Continuous(0.25), Continuous(0.5), None) {
+        Some(Continuous(v)) => assert_eq!(v, 0.5),
+        _ => fail!("expected a continuous value")
+    }
+    assert!(continuous_ring_bipolar.blend(BlendAdd, Discrete(1), Continuous(0.5), None).is_none());
+}
+
+#[test]
+fn test_mix() {
+    match continuous_ring_unipolar.mix(Continuous(0.1), Continuous(0.9), 0.5, None) {
+        Some(Continuous(v)) => assert!(v < 1e-9 || v > 1.0 - 1e-9),
+        _ => fail!("expected a continuous value")
+    }
+    match continuous_euclidian_unipolar.mix(Continuous(0.1), Continuous(0.9), 0.5, None) {
+        Some(Continuous(v)) => assert!((v - 0.5).abs() < 1e-9),
+        _ => fail!("expected a continuous value")
+    }
+    let r = Range { min: 0i64, max: 9 };
+    match discrete_ring.mix(Discrete(1), Discrete(7), 0.5, Some(&r)) {
+        Some(Discrete(9)) => (),
+        _ => fail!("expected the short way around, from 7 up through 9 to 1")
+    }
+    match discrete_set.mix(Discrete(1), Discrete(7), 0.4, Some(&r)) {
+        Some(Discrete(7)) => (),
+        _ => fail!("expected sets to snap")
+    }
+}
@@ -0,0 +1,309 @@
+//! Check a Profile for consistency before patching it.
+//!
+//! Nothing stops a profile author (or a profile file) from declaring a channel
+//! count that disagrees with the offsets its attributes write, overlapping
+//! attributes, out-of-bounds switch selections, or defaults of the wrong kind
+//! for their topo. Any of these would show up as a RenderFault in the middle
+//! of a show. Run validate_profile first, and refuse to patch a profile whose
+//! report is not valid.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use profile::*;
+use render::DmxIntIndexedWithRangeRenderer;
+
+/// One inconsistency found in a profile.
+#[deriving(Show)]
+pub enum ProblemKind {
+    /// An attribute writes a channel at or beyond the profile's channel count.
+    OffsetBeyondAllocation(uint),
+    /// The declared channel count is not the number of channels written.
+    /// (declared, written)
+    AllocationMismatch(uint, uint),
+    /// Two attributes that can render at the same time write the same channel.
+    /// Attributes in different children of a switch can't, so they may share.
+    OverlappingChannel(uint, String),
+    /// A DmxAddressOffsetMultiple lists fewer offsets than its renderer writes.
+    /// (needed, given)
+    MissingOffsets(uint, uint),
+    /// A switch's default selection is not one of its children.
+    /// (selection, child count)
+    DefaultSelectionOutOfBounds(uint, uint),
+    /// A Continuous default for a discrete topo, or vice versa.
+    MismatchedDefault,
+    /// The attribute renders to DMX but has no default.
+    MissingDefault,
+    /// An indexed attribute's default has no corresponding range.
+    DefaultIndexOutOfRange(i64),
+}
+
+pub struct Problem {
+    pub path: String,
+    pub kind: ProblemKind,
+}
+
+impl fmt::Show for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.kind)
+    }
+}
+
+/// The channels written by one attribute's renderer, in the order the
+/// renderer writes them (e.g. coarse, then fine).
+pub struct Footprint {
+    pub path: String,
+    pub channels: Vec<uint>,
+}
+
+pub struct ValidationReport {
+    pub problems: Vec<Problem>,
+    pub footprints: Vec<Footprint>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Walk the profile DAG and report every problem found, along with the
+/// footprint of every renderer. Shared nodes are checked once per path that
+/// reaches them.
+pub fn validate_profile(profile: &Profile) -> ValidationReport {
+    let mut report = ValidationReport { problems: Vec::new(), footprints: Vec::new() };
+    let writes = walk(&profile.root, "", &mut report);
+
+    match profile.chan_alloc {
+        DmxChannelCount(declared) => {
+            let mut written = 0u;
+            for &(channel, ref path) in writes.iter() {
+                if channel >= declared {
+                    report.problems.push(Problem {
+                        path: path.clone(),
+                        kind: OffsetBeyondAllocation(channel),
+                    });
+                }
+                if channel + 1 > written {
+                    written = channel + 1;
+                }
+            }
+            if written != declared {
+                report.problems.push(Problem {
+                    path: format!("/{}", profile.root.borrow().nickname()),
+                    kind: AllocationMismatch(declared, written),
+                });
+            }
+        }
+    }
+    report
+}
+
+/// Check node and its subgraph. Return the channels it may write while
+/// rendering, each with the path of the attribute that writes it.
+fn walk(node: &Rc<RefCell<ProfileGraph>>, parent: &str,
+        report: &mut ValidationReport) -> Vec<(uint, String)> {
+
+    let n = node.borrow();
+    let path = format!("{}/{}", parent, n.nickname());
+    match *n {
+        ProfileGraphAttribute(ref a) => check_attribute(a, path, report),
+        ProfileGraphBranch(ref b) => {
+            // All children render together, so none may share a channel.
+            let mut writes: Vec<(uint, String)> = Vec::new();
+            for child in b.children.iter() {
+                let child_writes = walk(child, path.as_slice(), report);
+                for &(channel, ref p) in child_writes.iter() {
+                    match writes.iter().find(|&&(c, _)| c == channel) {
+                        Some(&(_, ref other)) => report.problems.push(Problem {
+                            path: p.clone(),
+                            kind: OverlappingChannel(channel, other.clone()),
+                        }),
+                        None => ()
+                    }
+                }
+                writes.push_all_move(child_writes);
+            }
+            writes
+        },
+        ProfileGraphSwitch(ref s) => {
+            if s.default_selection >= s.children.len() {
+                report.problems.push(Problem {
+                    path: path.clone(),
+                    kind: DefaultSelectionOutOfBounds(s.default_selection, s.children.len()),
+                });
+            }
+            // Only one child renders at a time, so children may share.
+            let mut writes = Vec::new();
+            for child in s.children.iter() {
+                writes.push_all_move(walk(child, path.as_slice(), report));
+            }
+            writes
+        },
+    }
+}
+
+fn check_attribute(a: &Attribute, path: String,
+        report: &mut ValidationReport) -> Vec<(uint, String)> {
+
+    let mut kinds = Vec::new();
+
+    match a.default {
+        Some(Continuous(_)) if !a.topo.is_continuous() => kinds.push(MismatchedDefault),
+        Some(Discrete(_)) if a.topo.is_continuous() => kinds.push(MismatchedDefault),
+        _ => ()
+    }
+
+    let mut channels = Vec::new();
+    match a.dmx {
+        Some(ref dmx) => {
+            match (a.default, &dmx.renderer) {
+                (None, _) => kinds.push(MissingDefault),
+                (Some(Discrete(i)), &DmxIntIndexedWithRangeRenderer(_, ref range))
+                    if i < 0 || i >= range.len() as i64 => kinds.push(DefaultIndexOutOfRange(i)),
+                _ => ()
+            }
+
+            let needed = dmx.renderer.footprint();
+            for i in range(0u, needed) {
+                match dmx.offset.try_nth(i) {
+                    Some(c) => {
+                        if channels.contains(&c) {
+                            kinds.push(OverlappingChannel(c, path.clone()));
+                        }
+                        channels.push(c)
+                    },
+                    None => {
+                        kinds.push(MissingOffsets(needed, i));
+                        break;
+                    }
+                }
+            }
+        },
+        None => ()
+    }
+
+    for kind in kinds.move_iter() {
+        report.problems.push(Problem { path: path.clone(), kind: kind });
+    }
+    if a.dmx.is_none() {
+        return Vec::new();
+    }
+    let writes: Vec<(uint, String)> = channels.iter().map(|&c| (c, path.clone())).collect();
+    report.footprints.push(Footprint { path: path, channels: channels });
+    writes
+}
+
+#[test]
+fn test_validate_profile() {
+    use loader::load_profile;
+
+    let p = load_profile(r#"{
+        "name": "Fixture", "nickname": "Fix", "channels": 2,
+        "root": {"branch": "Fixture", "nickname": "Fix", "children": [
+            {"attribute": "Pan", "nickname": "Pan",
+             "effect": ["Position", "OrientationYoke", "Value"],
+             "topo": "continuous_ring_bipolar", "default": 0,
+             "dmx": {"offsets": [0, 2], "renderer": "double_big_endian"}},
+            {"switch": "Mode", "nickname": "Mode", "children": [
+                {"attribute": "A", "nickname": "A",
+                 "effect": ["ModeSelect", "Other", "Value"],
+                 "topo": "discrete_set", "default": 0,
+                 "dmx": {"offset": 1, "renderer": "int_indexed_with_range",
+                         "range": [[0, 127]]}},
+                {"attribute": "B", "nickname": "B",
+                 "effect": ["ModeSelect", "Other", "Value"],
+                 "topo": "discrete_set", "default": 0,
+                 "dmx": {"offset": 1, "renderer": "int_indexed_with_range",
+                         "range": [[128, 255]]}}
+            ]}
+        ]}
+    }"#).ok().unwrap();
+
+    let report = validate_profile(&p);
+    assert_eq!(report.footprints.len(), 3);
+    assert_eq!(report.footprints.get(0).channels, vec!(0u, 2));
+
+    // Channel 2 is beyond the 2 declared channels, so 3 are written. The
+    // switch children sharing channel 1 is fine.
+    assert_eq!(report.problems.len(), 2);
+    match report.problems.get(0).kind {
+        OffsetBeyondAllocation(2) => (),
+        _ => fail!("expected OffsetBeyondAllocation")
+    }
+    match report.problems.get(1).kind {
+        AllocationMismatch(2, 3) => (),
+        _ => fail!("expected AllocationMismatch")
+    }
+
+    let bad = load_profile(r#"{
+        "name": "Bad", "nickname": "Bad", "channels": 4,
+        "root": {"branch": "Bad", "nickname": "Bad", "children": [
+            {"attribute": "Dimmer", "nickname": "Dim",
+             "effect": ["Dimmer", "ColorspaceI", "Value"],
+             "topo": "continuous_euclidian_unipolar", "default": 0,
+             "dmx": {"offset": 0, "renderer": "float_unipolar"}},
+            {"attribute": "Strobe", "nickname": "Strobe",
+             "effect": ["Strobe", "Other", "Value"],
+             "topo": "continuous_euclidian_unipolar", "default": 0,
+             "dmx": {"offset": 0, "renderer": "float_unipolar"}},
+            {"attribute": "Zoom", "nickname": "Zoom",
+             "effect": ["Zoom", "Other", "Value"],
+             "topo": "continuous_euclidian_unipolar", "default": 0,
+             "dmx": {"offsets": [1], "renderer": "double_big_endian"}},
+            {"attribute": "Gobo", "nickname": "Gobo",
+             "effect": ["FilterSelect", "FilterSubtract", "Value"],
+             "topo": "discrete_set", "default": 5,
+             "dmx": {"offset": 2, "renderer": "int_indexed_with_range",
+                     "range": [[0, 127]]}},
+            {"switch": "Mode", "nickname": "Mode", "children": [
+                {"attribute": "Color", "nickname": "Color",
+                 "effect": ["ModeSelect", "Other", "Value"],
+                 "topo": "discrete_set", "default": 0,
+                 "dmx": {"offset": 3, "renderer": "int_indexed_with_range",
+                         "range": [[0, 255]]}}
+            ]}
+        ]}
+    }"#).ok().unwrap();
+
+    // The loader refuses these two, so break the loaded profile by hand.
+    match *bad.root.borrow() {
+        ProfileGraphBranch(ref b) => match *b.children.get(4).borrow_mut() {
+            ProfileGraphSwitch(ref mut s) => {
+                s.default_selection = 1;
+                match *s.children.get(0).borrow_mut() {
+                    ProfileGraphAttribute(ref mut a) => a.default = Some(Continuous(0.5)),
+                    _ => fail!("expected the Color attribute")
+                }
+            },
+            _ => fail!("expected the Mode switch")
+        },
+        _ => fail!("expected the Bad branch")
+    }
+
+    let report = validate_profile(&bad);
+    assert!(!report.is_valid());
+    assert_eq!(report.problems.len(), 5);
+    assert!(report.problems.iter().any(|p| match p.kind {
+        OverlappingChannel(0, ref other) =>
+            p.path.as_slice() == "/Bad/Strobe" && other.as_slice() == "/Bad/Dim",
+        _ => false
+    }));
+    assert!(report.problems.iter().any(|p| match p.kind {
+        MissingOffsets(2, 1) => p.path.as_slice() == "/Bad/Zoom",
+        _ => false
+    }));
+    assert!(report.problems.iter().any(|p| match p.kind {
+        DefaultIndexOutOfRange(5) => p.path.as_slice() == "/Bad/Gobo",
+        _ => false
+    }));
+    assert!(report.problems.iter().any(|p| match p.kind {
+        DefaultSelectionOutOfBounds(1, 1) => p.path.as_slice() == "/Bad/Mode",
+        _ => false
+    }));
+    assert!(report.problems.iter().any(|p| match p.kind {
+        MismatchedDefault => p.path.as_slice() == "/Bad/Mode/Color",
+        _ => false
+    }));
+}
@@ -1,23 +1,53 @@
 //! Types to help locate devices in the real world (or a fictional 3D scene).
 
 /// A Device's cartesian position in space.
+#[deriving(Clone,PartialEq,Show)]
 pub struct Position {
-    x: f64,
-    y: f64,
-    z: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
 }
 
-/// A Device's orientation in space.
+impl Position {
+    pub fn new(x: f64, y: f64, z: f64) -> Position {
+        Position { x: x, y: y, z: z }
+    }
+
+    pub fn dot(&self, other: &Position) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn length(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn distance(&self, other: &Position) -> f64 {
+        Position::new(self.x - other.x, self.y - other.y, self.z - other.z).length()
+    }
+
+    /// Scale to length 1. The origin stays put.
+    pub fn normalize(&self) -> Position {
+        let len = self.length();
+        if len == 0.0 {
+            return self.clone();
+        }
+        Position::new(self.x / len, self.y / len, self.z / len)
+    }
+}
+
+/// A Device's orientation in space, in degrees. See aim.rs for conventions.
+#[deriving(Clone,PartialEq,Show)]
 pub struct Orientation {
-    pan: f64,  // yaw
-    tilt: f64, // pitch
-    roll: f64, // roll
+    pub pan: f64,  // yaw
+    pub tilt: f64, // pitch
+    pub roll: f64, // roll
 }
 
 /// A Device's oriented position in space.
+#[deriving(Clone,PartialEq,Show)]
 pub struct Loc {
-    position: Position,
-    orientation: Orientation,
+    pub position: Position,
+    pub orientation: Orientation,
 }
 
 
@@ -29,4 +59,3 @@ impl Loc {
         }
     }
 }
-